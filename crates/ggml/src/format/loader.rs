@@ -5,18 +5,27 @@
 //! to process the data from the handler and construct your model.
 
 use std::{
+    collections::HashSet,
     error::Error,
     fmt,
     io::{BufRead, Seek, SeekFrom},
 };
 
+#[cfg(feature = "sha2")]
+use sha2::{Digest, Sha256};
+
 use crate::{
-    util::{has_data_left, read_bytes_with_len, read_f32, read_i32, read_u32},
+    util::{has_data_left, read_bytes_with_len_limited, read_f32, read_i32, read_u32},
     ContainerType, ElementType,
 };
 
+/// The maximum length of a vocabulary token or tensor name, to avoid huge allocations
+/// when reading corrupt files.
+pub(crate) const MAX_STRING_LENGTH: usize = 1024 * 1024;
+
 /// Helper struct that wraps the magic number of a file format,
 /// so that it can be printed in a human-readable format.
+#[derive(Clone, Copy)]
 pub struct FormatMagic(pub u32);
 impl fmt::Display for FormatMagic {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
@@ -46,6 +55,12 @@ pub enum LoadError<E: Error> {
     #[error("non-specific I/O error")]
     /// A non-specific IO error.
     Io(#[from] std::io::Error),
+    #[error("unexpected end of file while reading {while_reading}")]
+    /// The file ended while reading `while_reading`. This usually means that the file is truncated.
+    UnexpectedEof {
+        /// The part of the file that was being read.
+        while_reading: &'static str,
+    },
     #[error("could not convert bytes to a UTF-8 string")]
     /// One of the strings encountered was not valid UTF-8.
     InvalidUtf8(#[from] std::string::FromUtf8Error),
@@ -67,8 +82,44 @@ pub enum LoadError<E: Error> {
     /// An invariant was broken.
     InvariantBroken(String),
 }
+impl<E: Error + Clone> Clone for LoadError<E> {
+    /// Clones the error.
+    ///
+    /// [std::io::Error] is not [Clone], so I/O errors are cloned into a new error
+    /// with the same [kind](std::io::Error::kind) and message.
+    fn clone(&self) -> Self {
+        match self {
+            Self::InvalidMagic(magic) => Self::InvalidMagic(*magic),
+            Self::InvalidFormatVersion(container_type) => {
+                Self::InvalidFormatVersion(*container_type)
+            }
+            Self::Io(err) => Self::Io(std::io::Error::new(err.kind(), err.to_string())),
+            Self::UnexpectedEof { while_reading } => Self::UnexpectedEof { while_reading },
+            Self::InvalidUtf8(err) => Self::InvalidUtf8(err.clone()),
+            Self::InvalidIntegerConversion(err) => Self::InvalidIntegerConversion(*err),
+            Self::ImplementationError(err) => Self::ImplementationError(err.clone()),
+            Self::UnsupportedElementType { tensor_name, ftype } => Self::UnsupportedElementType {
+                tensor_name: tensor_name.clone(),
+                ftype: *ftype,
+            },
+            Self::InvariantBroken(invariant) => Self::InvariantBroken(invariant.clone()),
+        }
+    }
+}
+
+/// Returns a function that converts an I/O error encountered while reading `while_reading`
+/// into a [LoadError], reporting an end of file as [LoadError::UnexpectedEof].
+pub(crate) fn read_error<E: Error>(
+    while_reading: &'static str,
+) -> impl FnOnce(std::io::Error) -> LoadError<E> {
+    move |err| match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => LoadError::UnexpectedEof { while_reading },
+        _ => LoadError::Io(err),
+    }
+}
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Information about a [tensor](https://en.wikipedia.org/wiki/Tensor_(machine_learning)) that is being read.
 pub struct TensorLoadInfo {
     /// The name of the tensor.
@@ -78,6 +129,9 @@ pub struct TensorLoadInfo {
     /// The dimensions of the tensor.
     pub dims: [usize; 2],
     /// The number of elements in the tensor.
+    ///
+    /// A tensor with no dimensions is a scalar with one element; a tensor with a
+    /// zero-sized dimension has no elements.
     pub n_elements: usize,
     /// The type of the elements in the tensor.
     pub element_type: ElementType,
@@ -92,7 +146,7 @@ impl TensorLoadInfo {
 
     /// Calculate the size of the tensor's values in bytes.
     pub fn calc_size(&self) -> usize {
-        data_size(self.element_type, self.dims().iter().product())
+        data_size(self.element_type, n_elements(self.dims()))
     }
 
     /// Calculates the absolute size in bytes of the tensor's data, given the mmap flag.
@@ -104,18 +158,233 @@ impl TensorLoadInfo {
         }
     }
 
+    /// Returns the tensor's data within `mmap`, the memory-mapped file it was loaded from.
+    ///
+    /// Returns an [UnexpectedEof](std::io::ErrorKind::UnexpectedEof) error if the data
+    /// extends past the end of `mmap`, such as when the file is truncated.
+    pub fn mmap_data<'a>(&self, mmap: &'a [u8]) -> std::io::Result<&'a [u8]> {
+        usize::try_from(self.start_offset)
+            .ok()
+            .and_then(|start| mmap.get(start..start.checked_add(self.calc_size())?))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("the data of tensor {} is out of bounds", self.name),
+                )
+            })
+    }
+
     /// Reads the tensor's data from the given reader in an owned fashion.
     ///
     /// The behaviour is undefined if the reader does not correspond to this info.
     ///
     /// Do not use this if loading with `mmap`.
     pub fn read_data<R: BufRead + Seek>(&self, reader: &mut R) -> std::io::Result<Vec<u8>> {
-        let n_bytes = self.n_elements * crate::type_size(self.element_type);
-        let mut data = vec![0; n_bytes];
+        let mut data = vec![0; self.calc_size()];
         reader.seek(SeekFrom::Start(self.start_offset))?;
         reader.read_exact(&mut data)?;
         Ok(data)
     }
+
+    #[cfg(feature = "sha2")]
+    /// Reads the tensor's data from the given reader in an owned fashion, computing
+    /// the SHA-256 hash of the data as it is read.
+    ///
+    /// The behaviour is undefined if the reader does not correspond to this info.
+    ///
+    /// Do not use this if loading with `mmap`.
+    pub fn read_data_with_hash<R: BufRead + Seek>(
+        &self,
+        reader: &mut R,
+    ) -> std::io::Result<(Vec<u8>, [u8; 32])> {
+        let mut data = vec![0; self.calc_size()];
+        let mut hasher = Sha256::new();
+        reader.seek(SeekFrom::Start(self.start_offset))?;
+        for chunk in data.chunks_mut(HASH_CHUNK_SIZE) {
+            reader.read_exact(chunk)?;
+            hasher.update(chunk);
+        }
+        Ok((data, hasher.finalize().into()))
+    }
+}
+
+/// The number of bytes read at a time when hashing tensor data.
+#[cfg(feature = "sha2")]
+const HASH_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Computes the SHA-256 hash of the data of each of the `tensors`, returning the hashes
+/// keyed by tensor name.
+///
+/// The data is streamed from the reader and is not kept in memory.
+/// The behaviour is undefined if the reader does not correspond to the `tensors`.
+#[cfg(feature = "sha2")]
+pub fn tensor_hashes<'a, R: BufRead + Seek>(
+    reader: &mut R,
+    tensors: impl IntoIterator<Item = &'a TensorLoadInfo>,
+) -> std::io::Result<std::collections::HashMap<String, [u8; 32]>> {
+    let mut buffer = vec![0; HASH_CHUNK_SIZE];
+    tensors
+        .into_iter()
+        .map(|info| {
+            let mut hasher = Sha256::new();
+            let mut remaining = info.calc_size();
+            reader.seek(SeekFrom::Start(info.start_offset))?;
+            while remaining > 0 {
+                let chunk = &mut buffer[..remaining.min(HASH_CHUNK_SIZE)];
+                reader.read_exact(chunk)?;
+                hasher.update(&*chunk);
+                remaining -= chunk.len();
+            }
+            Ok((info.name.clone(), hasher.finalize().into()))
+        })
+        .collect()
+}
+
+/// Reads the data of each of the `tensors` in the order they appear in the file, and
+/// calls `f` with the tensor's name, its info, and its data.
+///
+/// The data is read into a buffer that is reused for every tensor, so only the largest
+/// tensor is kept in memory at a time. This allows transforming a model that does not
+/// fit in memory, one tensor at a time.
+/// The behaviour is undefined if the reader does not correspond to the `tensors`.
+///
+/// Do not use this if loading with `mmap`.
+pub fn for_each_tensor_data<'a, R: BufRead + Seek, F: FnMut(&str, &TensorLoadInfo, &[u8])>(
+    reader: &mut R,
+    tensors: impl IntoIterator<Item = &'a TensorLoadInfo>,
+    mut f: F,
+) -> std::io::Result<()> {
+    let mut tensors: Vec<_> = tensors.into_iter().collect();
+    tensors.sort_by_key(|info| info.start_offset);
+
+    let mut buffer = vec![];
+    for info in tensors {
+        buffer.resize(info.calc_size(), 0);
+        reader.seek(SeekFrom::Start(info.start_offset))?;
+        reader.read_exact(&mut buffer)?;
+        f(&info.name, info, &buffer);
+    }
+    Ok(())
+}
+
+/// Finds the tensor among `tensors` whose data contains the byte at `offset` from the
+/// start of the file.
+///
+/// Returns [None] if the byte is not part of any tensor's data, such as when it belongs
+/// to a header or to alignment padding.
+pub fn tensor_at_offset<'a>(
+    tensors: impl IntoIterator<Item = &'a TensorLoadInfo>,
+    offset: u64,
+) -> Option<&'a TensorLoadInfo> {
+    tensors.into_iter().find(|info| {
+        (info.start_offset..info.start_offset + info.calc_size() as u64).contains(&offset)
+    })
+}
+
+/// Finds the tensors among `tensors` that have a zero-sized dimension, and so no elements.
+///
+/// Such tensors can be loaded, but are usually a sign of a corrupted or badly converted
+/// file, such as one whose dimensions were truncated. Scalars, which have no dimensions,
+/// have one element and are not included.
+pub fn suspicious_tensors<'a>(
+    tensors: impl IntoIterator<Item = &'a TensorLoadInfo>,
+) -> Vec<&'a TensorLoadInfo> {
+    tensors
+        .into_iter()
+        .filter(|info| info.dims().contains(&0))
+        .collect()
+}
+
+/// Counts the elements of all of the `tensors`.
+///
+/// This is the number of `f32` values the model expands to once every tensor is
+/// dequantized; see [dequantized_bytes].
+pub fn total_elements<'a>(tensors: impl IntoIterator<Item = &'a TensorLoadInfo>) -> u64 {
+    tensors.into_iter().map(|info| info.n_elements as u64).sum()
+}
+
+/// Calculates the size in bytes of all of the `tensors` once dequantized to `f32`.
+///
+/// Compare this to the size of the file to tell whether a model still fits in memory
+/// after it is expanded.
+pub fn dequantized_bytes<'a>(tensors: impl IntoIterator<Item = &'a TensorLoadInfo>) -> u64 {
+    total_elements(tensors) * std::mem::size_of::<f32>() as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How the size of a file compares to the end of its tensor data.
+///
+/// See [verify_file_size].
+pub enum FileSizeCheck {
+    /// The file ends exactly where the tensor data ends.
+    ExactMatch,
+    /// The file has this many bytes after the end of the tensor data.
+    TrailingPadding(u64),
+    /// The file ends this many bytes before the end of the tensor data.
+    Truncated(u64),
+}
+
+/// Compares the size of the file read by `reader` to the end of the data of the `tensors`.
+///
+/// The loader seeks past each tensor's data without reading it, so a file whose last
+/// tensor's data is cut short still loads. Use this to tell such a truncated file apart
+/// from one with extra data at the end.
+pub fn verify_file_size<'a, R: Seek>(
+    reader: &mut R,
+    tensors: impl IntoIterator<Item = &'a TensorLoadInfo>,
+) -> std::io::Result<FileSizeCheck> {
+    let data_end = tensors
+        .into_iter()
+        .map(|info| info.start_offset + info.calc_size() as u64)
+        .max()
+        .unwrap_or(0);
+    let file_size = reader.seek(SeekFrom::End(0))?;
+
+    Ok(match file_size.cmp(&data_end) {
+        std::cmp::Ordering::Equal => FileSizeCheck::ExactMatch,
+        std::cmp::Ordering::Greater => FileSizeCheck::TrailingPadding(file_size - data_end),
+        std::cmp::Ordering::Less => FileSizeCheck::Truncated(data_end - file_size),
+    })
+}
+
+/// Reads the data of each of the `tensors` from the file at `path` in parallel,
+/// returning the data keyed by tensor name.
+///
+/// Each thread opens its own handle to the file, so the tensors are read independently.
+/// The behaviour is undefined if the file does not correspond to the `tensors`.
+///
+/// Do not use this if loading with `mmap`.
+#[cfg(feature = "rayon")]
+pub fn read_tensors_parallel<'a>(
+    path: &std::path::Path,
+    tensors: impl IntoIterator<Item = &'a TensorLoadInfo>,
+) -> std::io::Result<std::collections::HashMap<String, Vec<u8>>> {
+    use rayon::prelude::*;
+
+    let tensors: Vec<_> = tensors.into_iter().collect();
+    tensors
+        .into_par_iter()
+        .map_init(
+            || std::fs::File::open(path).map(std::io::BufReader::new),
+            |reader, info| {
+                let reader = reader
+                    .as_mut()
+                    .map_err(|e| std::io::Error::new(e.kind(), e.to_string()))?;
+                Ok((info.name.clone(), info.read_data(reader)?))
+            },
+        )
+        .collect()
+}
+
+/// Returns the number of elements in a tensor with the given dimensions.
+///
+/// A tensor with no dimensions is treated as a scalar, and has one element.
+/// A tensor with any zero-sized dimension is empty, and has no elements.
+pub(crate) fn n_elements(dims: &[usize]) -> usize {
+    match dims {
+        [] => 1,
+        dims => dims.iter().product(),
+    }
 }
 
 /// Returns the size occupied by a tensor's data in bytes given the element type and number of elements.
@@ -164,12 +433,8 @@ pub fn load<E: Error, R: BufRead + Seek>(
     // Verify magic
     let container_type = ContainerType::read(reader)?;
 
-    match container_type {
-        ContainerType::Ggml
-        | ContainerType::Ggmf(1)
-        | ContainerType::Ggjt(1..=3)
-        | ContainerType::Ggla(1) => {}
-        _ => return Err(LoadError::InvalidFormatVersion(container_type)),
+    if !container_type.is_supported() {
+        return Err(LoadError::InvalidFormatVersion(container_type));
     }
 
     handler
@@ -184,10 +449,15 @@ pub fn load<E: Error, R: BufRead + Seek>(
 
     // Load vocabulary
     for i in 0..n_vocab {
-        let len = read_u32(reader)?.try_into()?;
-        let token = read_bytes_with_len(reader, len)?;
+        let len = read_u32(reader)
+            .map_err(read_error("token length"))?
+            .try_into()?;
+        let token = read_bytes_with_len_limited(reader, len, MAX_STRING_LENGTH)
+            .map_err(read_error("token"))?;
         let token_score = match container_type {
-            ContainerType::Ggmf(_version) | ContainerType::Ggjt(_version) => read_f32(reader)?,
+            ContainerType::Ggmf(_version) | ContainerType::Ggjt(_version) => {
+                read_f32(reader).map_err(read_error("token score"))?
+            }
             ContainerType::Ggml | ContainerType::Ggla(_) => {
                 // Legacy model, set empty score
                 0.
@@ -216,13 +486,15 @@ fn load_weights<E: Error, R: BufRead + Seek>(
     handler: &mut impl LoadHandler<E>,
     align: bool,
 ) -> Result<(), LoadError<E>> {
+    let mut tensor_names = HashSet::new();
     while has_data_left(reader)? {
         // load tensor header
-        let n_dims: usize = read_i32(reader)?.try_into()?;
-        let name_len = read_i32(reader)?;
-        let ftype = read_u32(reader)?;
+        let n_dims: usize = read_i32(reader)
+            .map_err(read_error("tensor dimension count"))?
+            .try_into()?;
+        let name_len = read_i32(reader).map_err(read_error("tensor name length"))?;
+        let ftype = read_u32(reader).map_err(read_error("tensor element type"))?;
 
-        let mut n_elements: usize = 1;
         let mut dims = [1usize, 1];
         let ne_len = dims.len();
         if n_dims > ne_len {
@@ -231,13 +503,23 @@ fn load_weights<E: Error, R: BufRead + Seek>(
 
         #[allow(clippy::needless_range_loop)]
         for i in 0..n_dims {
-            let dim: usize = read_i32(reader)?.try_into()?;
+            let dim: usize = read_i32(reader)
+                .map_err(read_error("tensor dimension"))?
+                .try_into()?;
             dims[i] = dim;
-            n_elements *= dim;
         }
+        let n_elements = n_elements(&dims[0..n_dims]);
 
         // load tensor name
-        let name = String::from_utf8(read_bytes_with_len(reader, name_len.try_into()?)?)?;
+        let name = String::from_utf8(
+            read_bytes_with_len_limited(reader, name_len.try_into()?, MAX_STRING_LENGTH)
+                .map_err(read_error("tensor name"))?,
+        )?;
+        if !tensor_names.insert(name.clone()) {
+            return Err(LoadError::InvariantBroken(format!(
+                "duplicate tensor name: {name}"
+            )));
+        }
         let ftype =
             crate::Type::try_from(ftype).map_err(|_| LoadError::UnsupportedElementType {
                 tensor_name: name.clone(),
@@ -33,6 +33,7 @@ mod tests;
 pub type ElementType = Type;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The format of the file containing the model.
 pub enum ContainerType {
     /// Legacy format, oldest ggml tensor file format
@@ -44,7 +45,24 @@ pub enum ContainerType {
     /// LoRA adapter format.
     Ggla(u32),
 }
+/// The container types, including their versions, that [format::load] can read.
+pub const SUPPORTED_CONTAINER_TYPES: &[ContainerType] = &[
+    ContainerType::Ggml,
+    ContainerType::Ggmf(1),
+    ContainerType::Ggjt(1),
+    ContainerType::Ggjt(2),
+    ContainerType::Ggjt(3),
+    ContainerType::Ggla(1),
+];
+
 impl ContainerType {
+    /// Can this container type be read by [format::load]?
+    ///
+    /// See [SUPPORTED_CONTAINER_TYPES].
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED_CONTAINER_TYPES.contains(self)
+    }
+
     /// Does this container type support mmap?
     pub fn support_mmap(&self) -> bool {
         match self {
@@ -60,19 +78,19 @@ impl ContainerType {
         reader: &mut dyn std::io::BufRead,
     ) -> Result<Self, crate::format::LoadError<E>> {
         // Verify magic
-        let magic = util::read_u32(reader)?;
+        let magic = util::read_u32(reader).map_err(format::read_error("magic"))?;
         let container_type: ContainerType = match magic {
             crate::FILE_MAGIC_GGML => ContainerType::Ggml,
             crate::FILE_MAGIC_GGMF => {
-                let version = util::read_u32(reader)?;
+                let version = util::read_u32(reader).map_err(format::read_error("version"))?;
                 ContainerType::Ggmf(version)
             }
             crate::FILE_MAGIC_GGJT => {
-                let version = util::read_u32(reader)?;
+                let version = util::read_u32(reader).map_err(format::read_error("version"))?;
                 ContainerType::Ggjt(version)
             }
             crate::FILE_MAGIC_GGLA => {
-                let version = util::read_u32(reader)?;
+                let version = util::read_u32(reader).map_err(format::read_error("version"))?;
                 ContainerType::Ggla(version)
             }
             magic => {
@@ -117,6 +135,25 @@ pub const FILE_MAGIC_GGJT: u32 = 0x67676a74;
 /// Magic constant for `ggla` files (LoRA adapter).
 pub const FILE_MAGIC_GGLA: u32 = 0x67676C61;
 
+/// Returns whether `bytes`, the start of a file, begin with the magic constant of one of the
+/// container types.
+///
+/// This is a quick check to filter candidate files without reading them with [format::load];
+/// it does not check whether the version of the container type is supported.
+pub fn has_file_magic(bytes: &[u8]) -> bool {
+    let Some(magic) = bytes.get(..4) else {
+        return false;
+    };
+    let magic = u32::from_le_bytes(magic.try_into().unwrap());
+    [
+        FILE_MAGIC_GGML,
+        FILE_MAGIC_GGMF,
+        FILE_MAGIC_GGJT,
+        FILE_MAGIC_GGLA,
+    ]
+    .contains(&magic)
+}
+
 /// The current quantization version.
 pub const QNT_VERSION: u32 = sys::GGML_QNT_VERSION;
 /// The factor by which to divide `ftype` to determine the current quantization version.
@@ -152,6 +189,7 @@ impl Default for RoPEOverrides {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The type of a value in `ggml`.
 pub enum Type {
     /// Quantized 4-bit (type 0).
@@ -278,6 +316,33 @@ impl Type {
             Type::I8 => false,
         }
     }
+
+    /// Returns a rough ranking of how precisely this type stores floating-point values,
+    /// where a higher rank means a higher quality.
+    ///
+    /// Types are ranked by their bits per value, and the K-quantized types are ranked
+    /// above the other quantized types of the same size. This can be used to compare the
+    /// quantization of tensors or models. The integer types do not store floating-point
+    /// values, and are ranked 0.
+    pub fn quality_rank(&self) -> u8 {
+        match self {
+            Type::F32 => 13,
+            Type::F16 => 12,
+            Type::Q8_1 => 11,
+            Type::Q8_0 => 10,
+            Type::Q6_K => 9,
+            Type::Q5_1 => 8,
+            Type::Q5_K => 7,
+            Type::Q5_0 => 6,
+            Type::Q4_1 => 5,
+            Type::Q4_K => 4,
+            Type::Q4_0 => 3,
+            Type::Q3_K => 2,
+            Type::Q2_K => 1,
+            Type::I32 => 0,
+            Type::I8 => 0,
+        }
+    }
 }
 
 /// A buffer of memory that can be used as a scratch buffer for a [Context].
@@ -482,6 +547,54 @@ fn quantize_impl(
     QuantizationResult { output, history }
 }
 
+/// Dequantizes `src`, which contains values of type `t`, into `f32` values.
+///
+/// The values are returned in the same order as they are stored in `src`. Returns `None`
+/// if values of type `t` cannot be converted to `f32`, or if `src` does not contain a
+/// whole number of blocks of `t`.
+pub fn dequantize(t: Type, src: &[u8]) -> Option<Vec<f32>> {
+    let n_blocks = src.len() / type_size(t);
+    if n_blocks * type_size(t) != src.len() {
+        return None;
+    }
+    let n_elements = n_blocks * blck_size(t);
+
+    if t == Type::F32 {
+        return Some(
+            src.chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        );
+    }
+
+    let to_float = unsafe { sys::ggml_internal_get_type_traits(t.into()) }.to_float?;
+
+    // The `f16` conversion table used by `to_float` is only set up by the first `ggml_init`.
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| drop(Context::new_with_allocate(1024)));
+
+    // `to_float` takes the number of elements as an `i32`, so convert at most that many
+    // elements, in whole blocks, at a time.
+    let blocks_per_chunk = i32::MAX as usize / blck_size(t);
+    let elements_per_chunk = blocks_per_chunk * blck_size(t);
+
+    let mut output = vec![0f32; n_elements];
+    for (src, output) in src
+        .chunks(blocks_per_chunk * type_size(t))
+        .zip(output.chunks_mut(elements_per_chunk))
+    {
+        unsafe {
+            to_float(
+                src.as_ptr() as *const c_void,
+                output.as_mut_ptr(),
+                usize_to_i32(output.len()),
+            )
+        };
+    }
+
+    Some(output)
+}
+
 /// Returns true if the current system has BLAS support.
 pub fn cpu_has_blas() -> bool {
     unsafe { sys::ggml_cpu_has_blas() != 0 }
@@ -7,7 +7,7 @@ use std::{
 use crate::*;
 use rand::{distributions::Uniform, prelude::*};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct DummyError;
 impl std::fmt::Display for DummyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -96,15 +96,462 @@ fn roundtrip_test(
             .collect(),
     };
 
-    // Save the model.
+    roundtrip(save_container_type, &model)?;
+
+    Ok(())
+}
+
+#[test]
+fn can_roundtrip_scalar_and_empty_tensors() -> anyhow::Result<()> {
+    let element_type = crate::Type::F32;
+    let model = Model {
+        hyperparameters: Hyperparameters {
+            some_hyperparameter: random(),
+            some_other_hyperparameter: random(),
+            tokenizer_size: 0,
+        },
+        tokenizer: vec![],
+        tensors: [
+            (
+                "scalar".to_string(),
+                format::TensorSaveInfo {
+                    n_dims: 0,
+                    dims: [1, 1],
+                    element_type,
+                    data: 1.5f32.to_le_bytes().to_vec(),
+                },
+            ),
+            (
+                "empty".to_string(),
+                format::TensorSaveInfo {
+                    n_dims: 1,
+                    dims: [0, 1],
+                    element_type,
+                    data: vec![],
+                },
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    };
+
+    let (infos, _) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    let scalar = &infos["scalar"];
+    assert_eq!(scalar.dims(), &[] as &[usize]);
+    assert_eq!(scalar.n_elements, 1);
+    assert_eq!(scalar.calc_size(), 4);
+
+    let empty = &infos["empty"];
+    assert_eq!(empty.dims(), &[0]);
+    assert_eq!(empty.n_elements, 0);
+    assert_eq!(empty.calc_size(), 0);
+
+    let suspicious = format::suspicious_tensors(infos.values());
+    assert_eq!(suspicious.len(), 1);
+    assert_eq!(suspicious[0].name, "empty");
+
+    assert_eq!(format::total_elements(infos.values()), 1);
+    assert_eq!(format::dequantized_bytes(infos.values()), 4);
+
+    Ok(())
+}
+
+#[test]
+fn can_roundtrip_model_without_tensors() -> anyhow::Result<()> {
+    for (save_container_type, score) in [
+        (format::SaveContainerType::Ggml, 0.0),
+        (format::SaveContainerType::GgjtV3, 0.5),
+    ] {
+        let model = Model {
+            hyperparameters: Hyperparameters {
+                some_hyperparameter: random(),
+                some_other_hyperparameter: random(),
+                tokenizer_size: 2,
+            },
+            tokenizer: vec![
+                ("only".as_bytes().to_vec(), score),
+                ("metadata".as_bytes().to_vec(), score),
+            ],
+            tensors: BTreeMap::new(),
+        };
+
+        let (infos, _) = roundtrip(save_container_type, &model)?;
+        assert!(infos.is_empty());
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn can_hash_tensor_data() -> anyhow::Result<()> {
+    use sha2::{Digest, Sha256};
+
+    let model = f32_model((0..4).map(|i| (format!("tensor_{i}"), random_bytes(32))));
+    let (infos, buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    let mut reader = std::io::Cursor::new(&buffer);
+    let hashes = format::tensor_hashes(&mut reader, infos.values())?;
+    assert_eq!(hashes.len(), model.tensors.len());
+    for (name, info) in &infos {
+        let (data, hash) = info.read_data_with_hash(&mut reader)?;
+        let expected: [u8; 32] = Sha256::digest(&model.tensors[name].data).into();
+        assert_eq!(data, model.tensors[name].data);
+        assert_eq!(hash, expected);
+        assert_eq!(hashes[name], expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_stream_tensor_data() -> anyhow::Result<()> {
+    let model = f32_model((0..4).map(|i| (format!("tensor_{i}"), random_bytes(32))));
+    let (infos, buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    let mut offsets = vec![];
+    let mut reader = std::io::Cursor::new(&buffer);
+    format::for_each_tensor_data(&mut reader, infos.values(), |name, info, data| {
+        assert_eq!(data, model.tensors[name].data);
+        offsets.push(info.start_offset);
+    })?;
+    assert_eq!(offsets.len(), model.tensors.len());
+    assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+
+    Ok(())
+}
+
+#[test]
+fn can_find_tensor_at_offset() -> anyhow::Result<()> {
+    let model = f32_model((0..3).map(|i| (format!("tensor_{i}"), vec![0; 12])));
+    let (infos, _) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    for info in infos.values() {
+        let first = info.start_offset;
+        let last = first + info.calc_size() as u64 - 1;
+        for offset in [first, last] {
+            let found = format::tensor_at_offset(infos.values(), offset).unwrap();
+            assert_eq!(found.name, info.name);
+        }
+        // The byte before each tensor's data is part of its header or padding.
+        assert!(format::tensor_at_offset(infos.values(), first - 1).is_none());
+    }
+    assert!(format::tensor_at_offset(infos.values(), 0).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn can_verify_file_size() -> anyhow::Result<()> {
+    let model = f32_model([("tensor".to_string(), vec![0; 16])]);
+    let (infos, mut buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+    let check = |buffer: &[u8]| {
+        format::verify_file_size(&mut std::io::Cursor::new(buffer), infos.values()).unwrap()
+    };
+
+    assert_eq!(check(&buffer), format::FileSizeCheck::ExactMatch);
+    assert_eq!(
+        check(&buffer[..buffer.len() - 3]),
+        format::FileSizeCheck::Truncated(3)
+    );
+    buffer.extend([0; 5]);
+    assert_eq!(check(&buffer), format::FileSizeCheck::TrailingPadding(5));
+
+    Ok(())
+}
+
+#[test]
+fn can_slice_mmap_data() -> anyhow::Result<()> {
+    let model = f32_model([("tensor".to_string(), (0..16).collect())]);
+    let (infos, buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+    let info = &infos["tensor"];
+
+    assert_eq!(info.mmap_data(&buffer)?, model.tensors["tensor"].data);
+    let err = info.mmap_data(&buffer[..buffer.len() - 1]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn can_read_tensors_parallel() -> anyhow::Result<()> {
+    let model = f32_model((0..8).map(|i| (format!("tensor_{i}"), random_bytes(16))));
+    let (infos, buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    let path = std::env::temp_dir().join("ggml-can-read-tensors-parallel.bin");
+    std::fs::write(&path, &buffer)?;
+    let data = format::read_tensors_parallel(&path, infos.values());
+    std::fs::remove_file(&path)?;
+    let data = data?;
+
+    assert_eq!(data.len(), model.tensors.len());
+    for (name, tensor) in &model.tensors {
+        assert_eq!(data[name], tensor.data);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_clone_load_errors() {
+    let err = format::LoadError::<DummyError>::Io(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "file ended early",
+    ));
+    let format::LoadError::Io(cloned) = err.clone() else {
+        panic!("expected an I/O error");
+    };
+    assert_eq!(cloned.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(cloned.to_string(), "file ended early");
+
+    let err = format::LoadError::<DummyError>::ImplementationError(DummyError);
+    assert!(matches!(
+        err.clone(),
+        format::LoadError::ImplementationError(DummyError)
+    ));
+}
+
+#[test]
+fn will_report_truncated_files() -> anyhow::Result<()> {
+    let mut model = f32_model([("tensor".to_string(), vec![0; 16])]);
+    model.hyperparameters.tokenizer_size = 1;
+    model.tokenizer = vec![("truncated".as_bytes().to_vec(), 0.5)];
+    let (_, buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    // Each length cuts the file off partway through the named field: the magic, version,
+    // hyperparameters (12 bytes) and vocabulary end at byte 37, followed by the tensor header.
+    for (len, expected) in [
+        (2, "magic"),
+        (6, "version"),
+        (22, "token length"),
+        (26, "token"),
+        (35, "token score"),
+        (39, "tensor dimension count"),
+        (43, "tensor name length"),
+        (47, "tensor element type"),
+        (51, "tensor dimension"),
+        (55, "tensor name"),
+    ] {
+        let mut load_handler = MockLoadHandler {
+            data: &buffer,
+            loaded_model: Model::default(),
+            expected_container_type: ContainerType::Ggjt(3),
+            loaded_infos: BTreeMap::new(),
+        };
+        let err =
+            format::load(&mut std::io::Cursor::new(&buffer[..len]), &mut load_handler).unwrap_err();
+        let format::LoadError::UnexpectedEof { while_reading } = err else {
+            panic!("expected an unexpected end of file for {len} bytes, got {err:?}");
+        };
+        assert_eq!(while_reading, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn will_reject_oversized_token_length() -> anyhow::Result<()> {
+    let err = load_with_oversized_length(20, "token")?;
+    assert!(
+        matches!(&err, format::LoadError::Io(err) if err.kind() == std::io::ErrorKind::InvalidData),
+        "expected an invalid data error, got {err:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn will_reject_oversized_tensor_name_length() -> anyhow::Result<()> {
+    let err = load_with_oversized_length(37, "tensor")?;
+    assert!(
+        matches!(&err, format::LoadError::Io(err) if err.kind() == std::io::ErrorKind::InvalidData),
+        "expected an invalid data error, got {err:?}"
+    );
+    Ok(())
+}
+
+/// Saves a model with the token `token` and the tensor `tensor`, replaces the length of
+/// `string` at `offset` (the token length at 20, or the tensor name length at 37) with one
+/// over the maximum string length, and returns the error from loading it.
+fn load_with_oversized_length(
+    offset: usize,
+    string: &str,
+) -> anyhow::Result<format::LoadError<DummyError>> {
+    let mut model = f32_model([("tensor".to_string(), vec![0; 16])]);
+    model.hyperparameters.tokenizer_size = 1;
+    model.tokenizer = vec![("token".as_bytes().to_vec(), 0.5)];
+    let (_, mut buffer) = roundtrip(format::SaveContainerType::GgjtV3, &model)?;
+
+    assert_eq!(
+        buffer[offset..offset + 4],
+        (string.len() as u32).to_le_bytes()
+    );
+    let len = u32::try_from(format::MAX_STRING_LENGTH + 1)?;
+    buffer[offset..offset + 4].copy_from_slice(&len.to_le_bytes());
+
+    let mut load_handler = MockLoadHandler {
+        data: &buffer,
+        loaded_model: Model::default(),
+        expected_container_type: ContainerType::Ggjt(3),
+        loaded_infos: BTreeMap::new(),
+    };
+    Ok(format::load(&mut std::io::Cursor::new(&buffer), &mut load_handler).unwrap_err())
+}
+
+#[test]
+fn will_fail_on_duplicate_tensor_names() -> anyhow::Result<()> {
+    let model = f32_model([("output.weight".to_string(), vec![0; 16])]);
+    let buffer = save(
+        format::SaveContainerType::GgjtV3,
+        &model,
+        &["output.weight".to_string(), "output.weight".to_string()],
+    )?;
+
+    let mut load_handler = MockLoadHandler {
+        data: &buffer,
+        loaded_model: Model::default(),
+        expected_container_type: ContainerType::Ggjt(3),
+        loaded_infos: BTreeMap::new(),
+    };
+    let err = format::load(&mut std::io::Cursor::new(&buffer), &mut load_handler).unwrap_err();
+    let format::LoadError::InvariantBroken(invariant) = err else {
+        panic!("expected a broken invariant, got {err:?}");
+    };
+    assert_eq!(invariant, "duplicate tensor name: output.weight");
+
+    Ok(())
+}
+
+#[test]
+fn can_dequantize() {
+    let values: Vec<f32> = (0..32).map(|i| i as f32 - 16.0).collect();
+
+    let f32_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    assert_eq!(dequantize(Type::F32, &f32_bytes), Some(values.clone()));
+
+    // 1.0 and -2.0 as f16.
+    assert_eq!(
+        dequantize(Type::F16, &[0x00, 0x3C, 0x00, 0xC0]),
+        Some(vec![1.0, -2.0])
+    );
+
+    let q8_0 = quantize_q8_0(&values, values.len(), values.len());
+    let dequantized = dequantize(Type::Q8_0, &q8_0.output).unwrap();
+    assert_eq!(dequantized.len(), values.len());
+    for (dequantized, value) in dequantized.iter().zip(&values) {
+        assert!((dequantized - value).abs() < 0.1);
+    }
+
+    // A single Q4_K super-block: `d` = 1.0, `dmin` = 0.0, every sub-block scale = 1,
+    // every sub-block min = 0, so that each value is its 4-bit quant.
+    let mut q4_k = vec![0x00, 0x3C, 0x00, 0x00];
+    q4_k.extend([1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1]);
+    q4_k.extend((0..128u8).map(|i| (i % 16) | ((15 - i % 16) << 4)));
+    assert_eq!(q4_k.len(), type_size(Type::Q4_K));
+
+    // Each group of 32 bytes holds 64 values: the low nibbles, then the high nibbles.
+    let expected: Vec<f32> = (0..4)
+        .flat_map(|_| {
+            let low = (0..32).map(|i| (i % 16) as f32);
+            let high = (0..32).map(|i| (15 - i % 16) as f32);
+            low.chain(high)
+        })
+        .collect();
+    assert_eq!(dequantize(Type::Q4_K, &q4_k), Some(expected));
+
+    assert_eq!(dequantize(Type::I32, &[0; 4]), None);
+
+    // Partial blocks are rejected rather than dropped.
+    assert_eq!(dequantize(Type::F32, &f32_bytes[..5]), None);
+    assert_eq!(
+        dequantize(Type::Q8_0, &q8_0.output[..q8_0.output.len() - 1]),
+        None
+    );
+    assert_eq!(dequantize(Type::Q4_K, &q4_k[..q4_k.len() - 1]), None);
+}
+
+#[test]
+fn can_check_supported_container_types() {
+    assert!(ContainerType::Ggjt(3).is_supported());
+    assert!(!ContainerType::Ggjt(4).is_supported());
+    assert!(!ContainerType::Ggmf(2).is_supported());
+
     let mut buffer = Vec::new();
-    let mut cursor = std::io::Cursor::new(&mut buffer);
-    let mut save_handler = MockSaveHandler { model: &model };
-    format::save(
-        &mut cursor,
-        &mut save_handler,
+    ContainerType::Ggjt(4).write(&mut buffer).unwrap();
+    let mut load_handler = MockLoadHandler {
+        data: &buffer,
+        loaded_model: Model::default(),
+        expected_container_type: ContainerType::Ggjt(4),
+        loaded_infos: BTreeMap::new(),
+    };
+    assert!(matches!(
+        format::load(&mut std::io::Cursor::new(&buffer), &mut load_handler),
+        Err(format::LoadError::InvalidFormatVersion(
+            ContainerType::Ggjt(4)
+        ))
+    ));
+}
+
+#[test]
+fn can_check_file_magic() {
+    for container_type in crate::SUPPORTED_CONTAINER_TYPES {
+        let mut buffer = Vec::new();
+        container_type.write(&mut buffer).unwrap();
+        assert!(crate::has_file_magic(&buffer));
+    }
+    assert!(!crate::has_file_magic(b"GGUF"));
+    assert!(!crate::has_file_magic(
+        &crate::FILE_MAGIC_GGJT.to_le_bytes()[..3]
+    ));
+}
+
+#[test]
+fn can_rank_types_by_quality() {
+    let mut types = vec![
+        Type::Q4_0,
+        Type::F32,
+        Type::Q6_K,
+        Type::Q4_K,
+        Type::F16,
+        Type::Q8_0,
+    ];
+    types.sort_by_key(|t| std::cmp::Reverse(t.quality_rank()));
+    assert_eq!(
+        types,
+        vec![
+            Type::F32,
+            Type::F16,
+            Type::Q8_0,
+            Type::Q6_K,
+            Type::Q4_K,
+            Type::Q4_0
+        ]
+    );
+    assert_eq!(Type::I32.quality_rank(), 0);
+}
+
+#[test]
+fn can_limit_read_lengths() {
+    let data = [1u8, 2, 3, 4];
+
+    let bytes = util::read_bytes_with_len_limited(&mut &data[..], 4, 4).unwrap();
+    assert_eq!(bytes, data);
+
+    let err = util::read_bytes_with_len_limited(&mut &data[..], usize::MAX, 4).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+/// Saves and loads `model`, asserting that the loaded model is identical and
+/// returning the information for each loaded tensor, and the saved file.
+fn roundtrip(
+    save_container_type: format::SaveContainerType,
+    model: &Model,
+) -> anyhow::Result<(BTreeMap<String, format::TensorLoadInfo>, Vec<u8>)> {
+    // Save the model.
+    let buffer = save(
         save_container_type,
-        &model.tokenizer,
+        model,
         &model.tensors.keys().cloned().collect::<Vec<String>>(),
     )?;
 
@@ -114,11 +561,58 @@ fn roundtrip_test(
         data: &buffer,
         loaded_model: Model::default(),
         expected_container_type: save_container_type.into(),
+        loaded_infos: BTreeMap::new(),
     };
     format::load(&mut cursor, &mut load_handler)?;
-    assert_eq!(load_handler.loaded_model, model);
+    assert_eq!(&load_handler.loaded_model, model);
 
-    Ok(())
+    Ok((load_handler.loaded_infos, buffer))
+}
+
+/// Saves the `tensor_names` of `model`, returning the saved file.
+fn save(
+    save_container_type: format::SaveContainerType,
+    model: &Model,
+    tensor_names: &[String],
+) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    format::save(
+        &mut std::io::Cursor::new(&mut buffer),
+        &mut MockSaveHandler { model },
+        save_container_type,
+        &model.tokenizer,
+        tensor_names,
+    )?;
+    Ok(buffer)
+}
+
+/// Builds a model without a vocabulary, whose tensors are one-dimensional `F32` tensors
+/// with the given names and data.
+fn f32_model(tensors: impl IntoIterator<Item = (String, Vec<u8>)>) -> Model {
+    Model {
+        hyperparameters: Hyperparameters {
+            some_hyperparameter: random(),
+            some_other_hyperparameter: random(),
+            tokenizer_size: 0,
+        },
+        tokenizer: vec![],
+        tensors: tensors
+            .into_iter()
+            .map(|(name, data)| {
+                let tensor = format::TensorSaveInfo {
+                    n_dims: 1,
+                    dims: [data.len() / 4, 1],
+                    element_type: crate::Type::F32,
+                    data,
+                };
+                (name, tensor)
+            })
+            .collect(),
+    }
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|_| random()).collect()
 }
 
 #[derive(Default, PartialEq, Debug)]
@@ -173,6 +667,7 @@ struct MockLoadHandler<'a> {
     data: &'a [u8],
     loaded_model: Model,
     expected_container_type: ContainerType,
+    loaded_infos: BTreeMap<String, format::TensorLoadInfo>,
 }
 impl format::LoadHandler<DummyError> for MockLoadHandler<'_> {
     fn container_type(&mut self, container_type: ContainerType) -> Result<(), DummyError> {
@@ -210,7 +705,8 @@ impl format::LoadHandler<DummyError> for MockLoadHandler<'_> {
                 .read_data(&mut std::io::Cursor::new(self.data))
                 .unwrap(),
         };
-        self.loaded_model.tensors.insert(info.name, data);
+        self.loaded_model.tensors.insert(info.name.clone(), data);
+        self.loaded_infos.insert(info.name.clone(), info);
         Ok(())
     }
 }
@@ -47,6 +47,24 @@ pub fn read_bytes_with_len(
     Ok(bytes)
 }
 
+/// Read a variable-length array of bytes from a reader, failing if `len` exceeds `max_len`.
+///
+/// Use this when `len` was read from untrusted input, to avoid allocating a huge buffer
+/// for a corrupt length.
+pub fn read_bytes_with_len_limited(
+    reader: &mut dyn BufRead,
+    len: usize,
+    max_len: usize,
+) -> Result<Vec<u8>, std::io::Error> {
+    if len > max_len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Length {len} exceeds the maximum of {max_len}"),
+        ));
+    }
+    read_bytes_with_len(reader, len)
+}
+
 /// Write a `i32` from a writer.
 pub fn write_i32(writer: &mut dyn Write, value: i32) -> Result<(), std::io::Error> {
     writer.write_all(&value.to_le_bytes())
@@ -39,8 +39,11 @@ pub use model::{Hyperparameters, KnownModel, Model, ModelContext, ModelParameter
 pub use quantize::{quantize, QuantizeError, QuantizeProgress};
 pub use regex::Regex;
 pub use tokenizer::{
-    InvalidTokenBias, Prompt, TokenBias, TokenId, TokenizationError, Tokenizer, TokenizerLoadError,
-    TokenizerSource,
+    compare_tokenizations, ChatTemplate, EmbeddedTokenizer, EmbeddedTokenizerError,
+    HuggingFaceTokenizer, InvalidTokenBias, Prompt, PromptBuilder, PromptFormat, StopDecision,
+    StopSequenceMatcher, StreamDecoder, TokenBias, TokenId, TokenizationDivergence,
+    TokenizationError, Tokenizer, TokenizerFallbackError, TokenizerHandle, TokenizerLoadError,
+    TokenizerLoadErrorKind, TokenizerSource,
 };
 pub use util::TokenUtf8Buffer;
 
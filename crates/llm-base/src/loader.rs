@@ -243,6 +243,14 @@ pub enum LoadError {
     #[error("non-specific I/O error")]
     /// A non-specific IO error.
     Io(#[from] std::io::Error),
+    #[error("unexpected end of file while reading {while_reading} in {path:?}")]
+    /// The file ended while reading `while_reading`. This usually means that the file is truncated.
+    UnexpectedEof {
+        /// The path that failed.
+        path: PathBuf,
+        /// The part of the file that was being read.
+        while_reading: &'static str,
+    },
     #[error("could not convert bytes to a UTF-8 string")]
     /// One of the strings encountered was not valid UTF-8.
     InvalidUtf8(#[from] std::string::FromUtf8Error),
@@ -267,6 +275,20 @@ pub enum LoadError {
         /// The format that was encountered.
         container_type: ContainerType,
     },
+    #[error(
+        "quantization version {quantization_version} of {path:?} is not supported by this build, which supports version {}",
+        ggml::QNT_VERSION
+    )]
+    /// The quantized tensors in `path` use a layout that this version of `llm` cannot read.
+    ///
+    /// The layout of quantized blocks has changed between quantization versions, so reading
+    /// them with the wrong layout would produce incorrect weights.
+    UnsupportedQuantizationVersion {
+        /// The quantization version of the file.
+        quantization_version: u32,
+        /// The path that failed.
+        path: PathBuf,
+    },
     #[error("invalid value {ftype} for `f16` in hyperparameters")]
     /// The `f16` hyperparameter had an invalid value.
     HyperparametersF16Invalid {
@@ -282,6 +304,13 @@ pub enum LoadError {
         /// The path that failed.
         path: PathBuf,
     },
+    #[error("there is no tensor `{tensor_name}` in the model")]
+    /// The tensor `tensor_name` was looked up by name in a [Loader], but the model
+    /// does not have it.
+    TensorNotFound {
+        /// The name of the tensor.
+        tensor_name: String,
+    },
     #[error("the tensor `{tensor_name}` has the wrong size in {path:?}")]
     /// The tensor `tensor_name` did not match its expected size.
     TensorWrongSize {
@@ -374,6 +403,10 @@ impl LoadError {
                 LoadError::InvalidFormatVersion { container_type }
             }
             FormatLoadError::Io(err) => LoadError::Io(err),
+            FormatLoadError::UnexpectedEof { while_reading } => LoadError::UnexpectedEof {
+                path,
+                while_reading,
+            },
             FormatLoadError::InvalidUtf8(err) => LoadError::InvalidUtf8(err),
             FormatLoadError::InvalidIntegerConversion(err) => {
                 LoadError::InvalidIntegerConversion(err)
@@ -478,9 +511,13 @@ pub fn load<M: KnownModel>(
         quantization_version
     );
 
-    // TODO: this is temporary while we figure out how to handle this
-    if tensors.values().any(|t| t.element_type.is_quantized()) {
-        assert_eq!(quantization_version, 2, "quantization version must be 2");
+    if quantization_version != ggml::QNT_VERSION
+        && tensors.values().any(|t| t.element_type.is_quantized())
+    {
+        return Err(LoadError::UnsupportedQuantizationVersion {
+            quantization_version,
+            path: path.to_owned(),
+        });
     }
 
     let use_mmap =
@@ -570,6 +607,8 @@ pub fn load<M: KnownModel>(
 pub struct Loader<Hp: Hyperparameters, F: FnMut(LoadProgress)> {
     // Input
     load_progress_callback: F,
+    /// Whether the vocabulary in the file is loaded into the tokenizer.
+    load_vocabulary: bool,
 
     // Input/Output
     /// The tokenizer of the model.
@@ -585,9 +624,13 @@ pub struct Loader<Hp: Hyperparameters, F: FnMut(LoadProgress)> {
 }
 impl<Hp: Hyperparameters, F: FnMut(LoadProgress)> Loader<Hp, F> {
     /// Creates a new loader.
+    ///
+    /// The vocabulary in the file is only loaded into an empty embedded tokenizer; other
+    /// tokenizers, such as one read from a SentencePiece model, are kept as they are.
     pub fn new(tokenizer: Tokenizer, load_progress_callback: F) -> Self {
         Self {
             load_progress_callback,
+            load_vocabulary: matches!(&tokenizer, Tokenizer::Embedded(v) if v.is_empty()),
 
             container_type: ContainerType::Ggml,
             hyperparameters: Hp::default(),
@@ -595,6 +638,128 @@ impl<Hp: Hyperparameters, F: FnMut(LoadProgress)> Loader<Hp, F> {
             tensors: HashMap::default(),
         }
     }
+
+    /// Returns the information about the tensor called `name`, if it is in the model.
+    pub fn tensor_info(&self, name: &str) -> Option<&TensorLoadInfo> {
+        self.tensors.get(name)
+    }
+
+    /// Returns whether the model has a tensor called `name`.
+    pub fn contains_tensor(&self, name: &str) -> bool {
+        self.tensors.contains_key(name)
+    }
+
+    /// Returns the tensors that have `rank` dimensions.
+    ///
+    /// For example, a rank of 1 will return the bias and normalization tensors.
+    pub fn tensors_with_rank(
+        &self,
+        rank: usize,
+    ) -> impl Iterator<Item = (&str, &TensorLoadInfo)> + '_ {
+        self.tensors
+            .iter()
+            .filter(move |(_, info)| info.n_dims == rank)
+            .map(|(name, info)| (name.as_str(), info))
+    }
+
+    /// Returns the tensors that have exactly the dimensions `dims`.
+    pub fn tensors_with_dimensions<'a>(
+        &'a self,
+        dims: &'a [usize],
+    ) -> impl Iterator<Item = (&'a str, &'a TensorLoadInfo)> + 'a {
+        self.tensors
+            .iter()
+            .filter(move |(_, info)| info.dims() == dims)
+            .map(|(name, info)| (name.as_str(), info))
+    }
+
+    /// Returns the total size in bytes of the data of all of the tensors.
+    ///
+    /// The rest of the file consists of the hyperparameters, the vocabulary, the tensor
+    /// headers and any alignment padding, so its size is the size of the file minus this.
+    pub fn tensor_data_bytes(&self) -> usize {
+        self.tensors.values().map(|info| info.calc_size()).sum()
+    }
+
+    /// Renames the tensor `old` to `new`.
+    ///
+    /// Returns an error if there is no tensor named `old`, or if there is already
+    /// another tensor named `new`. Renaming a tensor to its own name does nothing.
+    pub fn rename_tensor(&mut self, old: &str, new: &str) -> Result<(), LoadError> {
+        if !self.tensors.contains_key(old) {
+            return Err(LoadError::TensorNotFound {
+                tensor_name: old.to_owned(),
+            });
+        }
+        if old == new {
+            return Ok(());
+        }
+        if self.tensors.contains_key(new) {
+            return Err(LoadError::InvariantBroken {
+                path: None,
+                invariant: format!("cannot rename tensor {old} to {new}, as {new} already exists"),
+            });
+        }
+
+        let mut info = self
+            .tensors
+            .remove(old)
+            .expect("the tensor was checked to exist");
+        info.name = new.to_owned();
+        self.tensors.insert(new.to_owned(), info);
+
+        Ok(())
+    }
+
+    /// Checks that the tensor `name` has exactly the dimensions `expected`.
+    ///
+    /// Returns an error describing the mismatch if it does not, so that model loaders can
+    /// reject malformed files before using the tensor.
+    pub fn validate_tensor_shape(&self, name: &str, expected: &[usize]) -> Result<(), LoadError> {
+        let info = self
+            .tensors
+            .get(name)
+            .ok_or_else(|| LoadError::TensorNotFound {
+                tensor_name: name.to_owned(),
+            })?;
+
+        if info.dims() != expected {
+            return Err(LoadError::InvariantBroken {
+                path: None,
+                invariant: format!(
+                    "tensor {name} has shape {:?}, expected {expected:?}",
+                    info.dims()
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reads the values of the tensor `name` from `reader`, dequantizing them to `f32`.
+    ///
+    /// `reader` must be the file that this loader has loaded. The values are returned
+    /// in the order they are stored in, with the first dimension varying fastest.
+    pub fn read_tensor_f32<R: BufRead + Seek>(
+        &self,
+        reader: &mut R,
+        name: &str,
+    ) -> Result<Vec<f32>, LoadError> {
+        let info = self
+            .tensor_info(name)
+            .ok_or_else(|| LoadError::TensorNotFound {
+                tensor_name: name.to_owned(),
+            })?;
+
+        let data = info.read_data(reader)?;
+        ggml::dequantize(info.element_type, &data).ok_or_else(|| {
+            LoadError::UnsupportedElementType {
+                tensor_name: name.to_owned(),
+                ftype: info.element_type.into(),
+                path: Default::default(),
+            }
+        })
+    }
 }
 impl<Hp: Hyperparameters, F: FnMut(LoadProgress)> ggml::format::LoadHandler<LoadError>
     for Loader<Hp, F>
@@ -605,6 +770,10 @@ impl<Hp: Hyperparameters, F: FnMut(LoadProgress)> ggml::format::LoadHandler<Load
     }
 
     fn vocabulary_token(&mut self, i: usize, token: Vec<u8>, score: f32) -> Result<(), LoadError> {
+        if !self.load_vocabulary {
+            return Ok(());
+        }
+
         if let Tokenizer::Embedded(mv) = &mut self.tokenizer {
             let id = match TokenId::try_from(i) {
                 Ok(id) => id,
@@ -715,6 +884,8 @@ impl<'a> FileContext<'a> {
         }
 
         let mut tensor = match dims {
+            // A tensor without dimensions is a scalar.
+            0 => self.context.new_tensor_1d(info.element_type, 1),
             1 => self.context.new_tensor_1d(info.element_type, ne[0]),
             2 => self.context.new_tensor_2d(info.element_type, ne[0], ne[1]),
             3 => self
@@ -724,7 +895,7 @@ impl<'a> FileContext<'a> {
                 return Err(LoadError::InvariantBroken {
                     path: Some(self.path.to_owned()),
                     invariant: format!(
-                        "the tensor {name} should have between 1 and 3 dimensions, not {dims}"
+                        "the tensor {name} should have between 0 and 3 dimensions, not {dims}"
                     ),
                 })
             }
@@ -732,8 +903,8 @@ impl<'a> FileContext<'a> {
 
         match self.context.storage().as_mmap() {
             Some(mmap) => unsafe {
-                let ptr = mmap.as_ptr().offset(info.start_offset as isize);
-                tensor.set_data(ptr as *mut std::ffi::c_void);
+                let data = info.mmap_data(mmap)?;
+                tensor.set_data(data.as_ptr() as *mut std::ffi::c_void);
             },
             None => {
                 let buf: &mut [u8] = unsafe {
@@ -14,7 +14,7 @@ use thiserror::Error;
 
 use crate::{
     loader::TensorLoader, tokenizer::TokenId, FileType, InferenceSession, InferenceSessionConfig,
-    LoadError, LoadProgress, Tokenizer, TokenizerSource,
+    LoadError, LoadProgress, TokenizationError, Tokenizer, TokenizerSource,
 };
 
 /// Common functions for model evaluation
@@ -127,6 +127,38 @@ pub trait Model: Send + Sync {
 
     /// Returns whether the model supports deleting tokens.
     fn supports_rewind(&self) -> bool;
+
+    /// Tokenize `text` with this model's tokenizer, returning only the token IDs.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted, and `eos`
+    /// controls whether this model's end-of-text token (see [Self::eot_token_id]) should be
+    /// appended.
+    fn tokenize_with_specials(
+        &self,
+        text: &str,
+        bos: bool,
+        eos: bool,
+    ) -> Result<Vec<TokenId>, TokenizationError> {
+        let mut tokens = self.tokenizer().tokenize_ids(text, bos)?;
+        if eos {
+            tokens.push(self.eot_token_id());
+        }
+        Ok(tokens)
+    }
+
+    /// Decode `tokens` with this model's tokenizer, stopping at the first end-of-text token
+    /// (see [Self::eot_token_id]).
+    ///
+    /// The end-of-text token and everything after it is dropped, and special tokens such as
+    /// the beginning-of-string token are skipped.
+    fn decode_until_eos(&self, tokens: &[TokenId]) -> Vec<u8> {
+        let eot_token_id = self.eot_token_id();
+        let end = tokens
+            .iter()
+            .position(|&id| id == eot_token_id)
+            .unwrap_or(tokens.len());
+        self.tokenizer().decode(tokens[..end].to_vec(), true)
+    }
 }
 impl<H: Hyperparameters, M: KnownModel<Hyperparameters = H>> Model for M {
     fn start_session(&self, config: InferenceSessionConfig) -> InferenceSession {
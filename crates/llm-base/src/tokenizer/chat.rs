@@ -0,0 +1,228 @@
+use super::{TokenId, TokenizationError, Tokenizer};
+
+#[derive(Clone, Debug, PartialEq, Default)]
+/// The text that surrounds each message in a chat prompt.
+///
+/// Models are trained with different chat formats, so these should match the format
+/// that the model expects.
+pub struct ChatTemplate {
+    /// The text inserted before the system message.
+    pub system_prefix: String,
+    /// The text inserted before each user message.
+    pub user_prefix: String,
+    /// The text inserted before each assistant message, and at the end of the prompt
+    /// so that the model generates the assistant's reply.
+    pub assistant_prefix: String,
+    /// The text inserted after each message.
+    pub message_suffix: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A chat format that can be detected from the special tokens of a tokenizer.
+///
+/// Only formats that use special tokens of their own are detected; formats that are
+/// written with regular text (such as Alpaca's `### Instruction:`) cannot be told apart
+/// from the tokenizer alone.
+pub enum PromptFormat {
+    /// The ChatML format, which wraps each message in `<|im_start|>` and `<|im_end|>`.
+    ChatMl,
+    /// The Llama 3 format, which wraps each role in `<|start_header_id|>` and
+    /// `<|end_header_id|>`, and ends each message with `<|eot_id|>`.
+    Llama3,
+}
+impl PromptFormat {
+    /// Guess the format from the special tokens in `tokenizer`.
+    ///
+    /// Returns `None` if the tokenizer does not have all of the special tokens of exactly
+    /// one of the formats.
+    pub(crate) fn detect(tokenizer: &Tokenizer) -> Option<Self> {
+        let has_tokens = |tokens: &[&str]| {
+            tokens
+                .iter()
+                .all(|token| tokenizer.id(token.as_bytes()).is_some())
+        };
+
+        let mut formats = [
+            (Self::ChatMl, &["<|im_start|>", "<|im_end|>"][..]),
+            (
+                Self::Llama3,
+                &["<|start_header_id|>", "<|end_header_id|>", "<|eot_id|>"][..],
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, tokens)| has_tokens(tokens))
+        .map(|(format, _)| format);
+
+        match (formats.next(), formats.next()) {
+            (Some(format), None) => Some(format),
+            _ => None,
+        }
+    }
+
+    /// The template for building prompts in this format.
+    pub fn template(&self) -> ChatTemplate {
+        match self {
+            Self::ChatMl => ChatTemplate {
+                system_prefix: "<|im_start|>system\n".to_string(),
+                user_prefix: "<|im_start|>user\n".to_string(),
+                assistant_prefix: "<|im_start|>assistant\n".to_string(),
+                message_suffix: "<|im_end|>\n".to_string(),
+            },
+            Self::Llama3 => ChatTemplate {
+                system_prefix: "<|start_header_id|>system<|end_header_id|>\n\n".to_string(),
+                user_prefix: "<|start_header_id|>user<|end_header_id|>\n\n".to_string(),
+                assistant_prefix: "<|start_header_id|>assistant<|end_header_id|>\n\n".to_string(),
+                message_suffix: "<|eot_id|>".to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Builds a chat prompt from a system message and user and assistant turns.
+///
+/// The system message always comes first, followed by the turns in the order they
+/// were added. The prompt ends with the assistant prefix, so that the model continues
+/// with the assistant's reply.
+pub struct PromptBuilder {
+    template: ChatTemplate,
+    system: Option<String>,
+    turns: Vec<(Role, String)>,
+}
+impl PromptBuilder {
+    /// Create a new, empty builder that formats messages with `template`.
+    pub fn new(template: ChatTemplate) -> Self {
+        Self {
+            template,
+            system: None,
+            turns: vec![],
+        }
+    }
+
+    /// Set the system message, replacing any previous one.
+    pub fn system(mut self, message: impl Into<String>) -> Self {
+        self.system = Some(message.into());
+        self
+    }
+
+    /// Add a user turn.
+    pub fn user(mut self, message: impl Into<String>) -> Self {
+        self.turns.push((Role::User, message.into()));
+        self
+    }
+
+    /// Add an assistant turn.
+    pub fn assistant(mut self, message: impl Into<String>) -> Self {
+        self.turns.push((Role::Assistant, message.into()));
+        self
+    }
+
+    /// Build the text of the prompt, ending with the assistant prefix.
+    pub fn build_for_generation(&self) -> String {
+        let ChatTemplate {
+            system_prefix,
+            user_prefix,
+            assistant_prefix,
+            message_suffix,
+        } = &self.template;
+
+        let mut text = String::new();
+        if let Some(system) = &self.system {
+            text += &format!("{system_prefix}{system}{message_suffix}");
+        }
+        for (role, message) in &self.turns {
+            let prefix = match role {
+                Role::User => user_prefix,
+                Role::Assistant => assistant_prefix,
+            };
+            text += &format!("{prefix}{message}{message_suffix}");
+        }
+        text += assistant_prefix;
+        text
+    }
+
+    /// Build the prompt and tokenize it with `tokenizer`.
+    ///
+    /// The whole prompt is tokenized at once, so if `beginning_of_sentence` is true,
+    /// the beginning-of-string token appears exactly once, at the start.
+    pub fn to_tokens(
+        &self,
+        tokenizer: &Tokenizer,
+        beginning_of_sentence: bool,
+    ) -> Result<Vec<TokenId>, TokenizationError> {
+        tokenizer.tokenize_ids(&self.build_for_generation(), beginning_of_sentence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedTokenizer;
+
+    fn template() -> ChatTemplate {
+        ChatTemplate {
+            system_prefix: "S:".to_string(),
+            user_prefix: "U:".to_string(),
+            assistant_prefix: "A:".to_string(),
+            message_suffix: "\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_build_for_generation() {
+        let builder = PromptBuilder::new(template())
+            .user("hi")
+            .assistant("hello")
+            .user("bye")
+            .system("be nice");
+
+        assert_eq!(
+            builder.build_for_generation(),
+            "S:be nice\nU:hi\nA:hello\nU:bye\nA:"
+        );
+    }
+
+    #[test]
+    fn test_detect_prompt_format() {
+        let tokenizer = |tokens: &[&str]| -> Tokenizer {
+            let mut tokenizer = EmbeddedTokenizer::default();
+            for (id, token) in ["<unk>", "<s>", "</s>"].iter().chain(tokens).enumerate() {
+                tokenizer.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+            }
+            tokenizer.into()
+        };
+
+        let chatml = tokenizer(&["<|im_start|>", "<|im_end|>"]);
+        assert_eq!(chatml.detect_prompt_format(), Some(PromptFormat::ChatMl));
+
+        let llama3 = tokenizer(&["<|start_header_id|>", "<|end_header_id|>", "<|eot_id|>"]);
+        assert_eq!(llama3.detect_prompt_format(), Some(PromptFormat::Llama3));
+
+        assert_eq!(tokenizer(&["<|im_start|>"]).detect_prompt_format(), None);
+        assert_eq!(tokenizer(&[]).detect_prompt_format(), None);
+    }
+
+    #[test]
+    fn test_to_tokens_has_one_bos() {
+        let mut tokenizer = EmbeddedTokenizer::default();
+        for (id, token) in ["<unk>", "<s>", "</s>", "U:", "A:", "hi", "\n"]
+            .iter()
+            .enumerate()
+        {
+            tokenizer.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+        }
+        let tokenizer = tokenizer.into();
+
+        let builder = PromptBuilder::new(template()).user("hi").user("hi");
+        assert_eq!(
+            builder.to_tokens(&tokenizer, true).unwrap(),
+            vec![1, 3, 5, 6, 3, 5, 6, 4]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+use super::{TokenId, TokenizationError, Tokenizer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A run of tokens where two tokenizations of the same text differ.
+///
+/// See [compare_tokenizations].
+pub struct TokenizationDivergence {
+    /// The range of the differing tokens in the first tokenization.
+    pub a_range: Range<usize>,
+    /// The differing tokens in the first tokenization.
+    pub a_tokens: Vec<TokenId>,
+    /// The range of the differing tokens in the second tokenization.
+    pub b_range: Range<usize>,
+    /// The differing tokens in the second tokenization.
+    pub b_tokens: Vec<TokenId>,
+}
+
+/// Tokenizes `text` with both `a` and `b`, and returns the runs of tokens where they differ.
+///
+/// The tokenizations are aligned on their longest common subsequence of token IDs, so a
+/// difference in one place does not cause the rest of the text to be reported as different.
+/// This is intended for checking that two tokenizers for the same model (e.g. the embedded
+/// vocabulary and the Hugging Face tokenizer) agree, so it assumes that they share token IDs.
+pub fn compare_tokenizations(
+    a: &Tokenizer,
+    b: &Tokenizer,
+    text: &str,
+) -> Result<Vec<TokenizationDivergence>, TokenizationError> {
+    let token_ids = |tokenizer: &Tokenizer| -> Result<Vec<TokenId>, TokenizationError> {
+        Ok(tokenizer
+            .tokenize(text, false)?
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect())
+    };
+    let a = token_ids(a)?;
+    let b = token_ids(b)?;
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut divergences = vec![];
+    let (mut i, mut j) = (0, 0);
+    let (mut a_start, mut b_start) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            if (a_start, b_start) != (i, j) {
+                divergences.push(divergence(&a, a_start..i, &b, b_start..j));
+            }
+            i += 1;
+            j += 1;
+            (a_start, b_start) = (i, j);
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if (a_start, b_start) != (i, j) {
+        divergences.push(divergence(&a, a_start..i, &b, b_start..j));
+    }
+
+    Ok(divergences)
+}
+
+fn divergence(
+    a: &[TokenId],
+    a_range: Range<usize>,
+    b: &[TokenId],
+    b_range: Range<usize>,
+) -> TokenizationDivergence {
+    TokenizationDivergence {
+        a_tokens: a[a_range.clone()].to_vec(),
+        a_range,
+        b_tokens: b[b_range.clone()].to_vec(),
+        b_range,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedTokenizer;
+
+    fn tokenizer(tokens: &[&str]) -> Tokenizer {
+        let mut tokenizer = EmbeddedTokenizer::default();
+        for (id, token) in tokens.iter().enumerate() {
+            tokenizer.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+        }
+        tokenizer.into()
+    }
+
+    #[test]
+    fn test_compare_tokenizations() {
+        let a = tokenizer(&["<unk>", "<s>", "</s>", "Hel", "lo", " world", "Hello"]);
+        let b = tokenizer(&["<unk>", "<s>", "</s>", "Hel", "lo", " world", "-"]);
+
+        assert_eq!(
+            compare_tokenizations(&a, &a, "Hello world").unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            compare_tokenizations(&a, &b, "Hello worldHello").unwrap(),
+            vec![
+                TokenizationDivergence {
+                    a_range: 0..1,
+                    a_tokens: vec![6],
+                    b_range: 0..2,
+                    b_tokens: vec![3, 4],
+                },
+                TokenizationDivergence {
+                    a_range: 2..3,
+                    a_tokens: vec![6],
+                    b_range: 3..5,
+                    b_tokens: vec![3, 4],
+                },
+            ]
+        );
+    }
+}
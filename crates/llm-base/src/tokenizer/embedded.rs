@@ -1,15 +1,63 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, cmp::Reverse, collections::HashMap, error::Error, ops::Range};
 
 use thiserror::Error;
+use tokenizers::{
+    decoders::{byte_fallback::ByteFallback, fuse::Fuse, sequence::Sequence, strip::Strip},
+    models::unigram::Unigram,
+    normalizers::{self, Prepend, Replace},
+    processors::template::TemplateProcessing,
+    AddedToken,
+};
 
 use super::{Token, TokenId, TokenScore, TokenizationError};
 
+/// The SentencePiece metasymbol used in place of spaces (`▁`).
+pub(crate) const SENTENCEPIECE_SPACE: &[u8] = "\u{2581}".as_bytes();
+
 #[derive(Debug, Error)]
 /// Errors that can occur when using a model tokenizer.
 pub enum EmbeddedTokenizerError {
     /// Arbitrary error that occurred during use of the model tokenizer.
     #[error("Arbitrary error: {0:?}")]
     Arbitrary(String),
+    /// A special token ID does not belong to the vocabulary.
+    #[error("the {name} token ID {id} is not in the vocabulary of {len} tokens")]
+    InvalidSpecialTokenId {
+        /// The name of the special token.
+        name: &'static str,
+        /// The ID of the special token.
+        id: TokenId,
+        /// The number of tokens in the vocabulary.
+        len: usize,
+    },
+    /// The tokenizer was created without the map from tokens to IDs, so it cannot tokenize.
+    #[error("the tokenizer can only decode, as it was created without a token to ID map")]
+    DecodeOnly,
+    /// The text to tokenize is longer than the configured maximum.
+    #[error(
+        "the text to tokenize is {len} bytes long, more than the maximum of {max_input_bytes}"
+    )]
+    InputTooLong {
+        /// The length of the text in bytes.
+        len: usize,
+        /// The maximum length of the text in bytes.
+        max_input_bytes: usize,
+    },
+    /// A token is not valid UTF-8, and is longer than a byte, so it cannot be exported.
+    #[error("the token {id} is not valid UTF-8, so it cannot be exported")]
+    NonUtf8Token {
+        /// The ID of the token.
+        id: TokenId,
+    },
+    /// The internal maps of the tokenizer do not agree with each other.
+    #[error("the tokenizer is inconsistent: {0}")]
+    Inconsistent(String),
+    /// The SentencePiece model could not be read.
+    #[error("invalid SentencePiece model: {0}")]
+    InvalidSentencePieceModel(String),
+    /// The Hugging Face tokenizer could not be built from this tokenizer.
+    #[error("could not export the tokenizer: {0}")]
+    Export(#[source] Box<dyn Error + Send + Sync>),
 }
 
 /// The built-in GGML tokenizer.
@@ -27,9 +75,37 @@ pub struct EmbeddedTokenizer {
 
     /// The longest token in this tokenizer.
     max_token_length: usize,
+
+    /// Whether a space should be inserted before the text to tokenize, matching the
+    /// behaviour of SentencePiece models trained with `add_dummy_prefix`.
+    add_dummy_prefix: bool,
+
+    /// The token used to cover characters that no token in the vocabulary can represent.
+    unk_token_id: Option<TokenId>,
+
+    /// Whether `token_to_id` is left empty, as the tokenizer is only used for decoding.
+    decode_only: bool,
+
+    /// The length in bytes of the longest text that can be tokenized.
+    max_input_bytes: Option<usize>,
+
+    /// The tokens that are split from the text when tokenizing with special tokens.
+    special_token_ids: Vec<TokenId>,
 }
 
 impl EmbeddedTokenizer {
+    /// Create a new, empty tokenizer that can only decode.
+    ///
+    /// The map from tokens to IDs is not built as tokens are added, which saves its memory
+    /// for vocabularies that are only used to decode token IDs. [Tokenizer::id](super::Tokenizer::id)
+    /// returns `None` for every token, and tokenizing fails with [EmbeddedTokenizerError::DecodeOnly].
+    pub fn new_decode_only() -> Self {
+        Self {
+            decode_only: true,
+            ..Default::default()
+        }
+    }
+
     /// Add a token to the internal vocabulary.
     ///
     /// The token added must have `id` directly after the last token in the vocabulary.
@@ -49,18 +125,154 @@ impl EmbeddedTokenizer {
         self.max_token_length = self.max_token_length.max(content.len());
         self.id_to_token.push(content.clone());
         self.id_to_token_score.push(score);
-        self.token_to_id.insert(content, id);
+        if !self.decode_only {
+            self.token_to_id.insert(content, id);
+        }
+    }
+
+    /// Sets whether a space should be inserted before the text to tokenize.
+    ///
+    /// SentencePiece models trained with `add_dummy_prefix` (such as LLaMA) expect the first
+    /// word of the text to be preceded by a space, so that it is tokenized the same way as
+    /// any other word. GGML vocabularies store the SentencePiece space metasymbol as a
+    /// regular space, so a space is inserted. This is disabled by default.
+    pub fn set_add_dummy_prefix(&mut self, add_dummy_prefix: bool) {
+        self.add_dummy_prefix = add_dummy_prefix;
+    }
+
+    /// Returns whether a dummy prefix is added; see [Self::set_add_dummy_prefix].
+    pub(crate) fn add_dummy_prefix(&self) -> bool {
+        self.add_dummy_prefix
+    }
+
+    /// Sets the token used to cover characters that are not in the vocabulary.
+    ///
+    /// By default, tokenizing text containing such a character fails. When an unknown token
+    /// is set, each uncovered character is instead tokenized as this token. It is only used
+    /// as a last resort, so it never replaces a segmentation made of vocabulary tokens.
+    pub fn set_unk_token_id(&mut self, unk_token_id: Option<TokenId>) {
+        self.unk_token_id = unk_token_id;
+    }
+
+    /// Sets the tokens that are recognized in the text when tokenizing with special tokens.
+    ///
+    /// GGML vocabularies do not record which tokens are special, so by default, none are
+    /// recognized, and control strings such as `</s>` in the text may be merged with the text
+    /// around them or split into smaller tokens. See [Tokenizer::tokenize_with_special](super::Tokenizer::tokenize_with_special).
+    pub fn set_special_token_ids(&mut self, special_token_ids: Vec<TokenId>) {
+        self.special_token_ids = special_token_ids;
+    }
+
+    /// Sets the length in bytes of the longest text that can be tokenized.
+    ///
+    /// Tokenization allocates memory proportional to the length of the text, so this can be
+    /// used to reject very long texts before doing so. Tokenizing a longer text fails with
+    /// [EmbeddedTokenizerError::InputTooLong]. By default, there is no limit.
+    pub fn set_max_input_bytes(&mut self, max_input_bytes: Option<usize>) {
+        self.max_input_bytes = max_input_bytes;
+    }
+
+    /// Checks that the special token IDs used by this tokenizer belong to its vocabulary.
+    ///
+    /// The beginning-of-string token is always ID 1, the unknown token is set with
+    /// [Self::set_unk_token_id], and the other special tokens with
+    /// [Self::set_special_token_ids]. Using an ID outside of the vocabulary would make
+    /// tokenization or decoding panic or fail, so call this after loading a vocabulary
+    /// from an untrusted file.
+    pub fn validate_special_tokens(&self) -> Result<(), EmbeddedTokenizerError> {
+        let special_tokens = [
+            ("beginning-of-string", Some(1)),
+            ("unknown", self.unk_token_id),
+        ]
+        .into_iter()
+        .chain(
+            self.special_token_ids
+                .iter()
+                .map(|&id| ("special", Some(id))),
+        );
+        for (name, id) in special_tokens {
+            if let Some(id) = id.filter(|&id| id as usize >= self.len()) {
+                return Err(EmbeddedTokenizerError::InvalidSpecialTokenId {
+                    name,
+                    id,
+                    len: self.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the tokens, their scores and the map from tokens to IDs agree with each other.
+    ///
+    /// Every token must have a score, and map back to an ID of the same token. Tokens that
+    /// appear more than once in the vocabulary map to one of their IDs.
+    pub fn validate(&self) -> Result<(), EmbeddedTokenizerError> {
+        let inconsistent = |reason| Err(EmbeddedTokenizerError::Inconsistent(reason));
+
+        if self.id_to_token.len() != self.id_to_token_score.len() {
+            return inconsistent(format!(
+                "there are {} tokens, but {} scores",
+                self.id_to_token.len(),
+                self.id_to_token_score.len()
+            ));
+        }
+        if self.decode_only {
+            return Ok(());
+        }
+
+        for (token, &id) in &self.token_to_id {
+            if self.id_to_token.get(id as usize) != Some(token) {
+                return inconsistent(format!("a token maps to the ID {id} of another token"));
+            }
+        }
+        for (id, token) in self.id_to_token.iter().enumerate() {
+            if !self.token_to_id.contains_key(token) {
+                return inconsistent(format!("the token {id} does not map to an ID"));
+            }
+        }
+        Ok(())
     }
 
     pub(crate) fn id(&self, token: &[u8]) -> Option<TokenId> {
         self.token_to_id.get(token).copied()
     }
 
+    /// Converts a word to the token ID it represents, applying the same normalization as
+    /// tokenization.
+    ///
+    /// If [Self::set_add_dummy_prefix] is enabled, a space is inserted before `text`. Spaces
+    /// are looked up as they are, then as the SentencePiece metasymbol (`▁`), as vocabularies
+    /// may store either.
+    pub(crate) fn id_normalized(&self, text: &str) -> Option<TokenId> {
+        let text = self.normalize(text);
+        self.id(text.as_bytes()).or_else(|| {
+            let metasymbol = std::str::from_utf8(SENTENCEPIECE_SPACE).unwrap();
+            self.id(text.replace(' ', metasymbol).as_bytes())
+        })
+    }
+
+    /// Returns the text that tokenization operates on, with the dummy prefix added if enabled.
+    ///
+    /// The vocabulary stores the SentencePiece metasymbol (`▁`) as a space, so spaces are kept.
+    pub(crate) fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.add_dummy_prefix && !text.is_empty() {
+            Cow::Owned(format!(" {text}"))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
     /// Converts a token index to the token it represents in this tokenizer.
     pub(crate) fn token(&self, idx: usize) -> Vec<u8> {
         self.id_to_token[idx].clone()
     }
 
+    /// Returns the bytes of the token at `idx` without copying them, or `None` if there is no
+    /// such token.
+    pub fn token_bytes(&self, idx: usize) -> Option<&[u8]> {
+        self.id_to_token.get(idx).map(Vec::as_slice)
+    }
+
     /// Returns the number of tokens in the tokenizer.
     pub(crate) fn len(&self) -> usize {
         self.id_to_token.len()
@@ -71,6 +283,28 @@ impl EmbeddedTokenizer {
         self.id_to_token.is_empty()
     }
 
+    /// Returns the length in bytes of the longest token in the vocabulary.
+    pub(crate) fn max_token_byte_length(&self) -> usize {
+        self.max_token_length
+    }
+
+    /// Returns the longest token that `text` starts with, and its length in bytes.
+    pub(crate) fn longest_prefix_token(&self, text: &[u8]) -> Option<(TokenId, usize)> {
+        (1..=text.len().min(self.max_token_length))
+            .rev()
+            .find_map(|len| Some((*self.token_to_id.get(&text[..len])?, len)))
+    }
+
+    /// Returns the IDs of the tokens that are not valid UTF-8 by themselves.
+    pub(crate) fn non_utf8_token_ids(&self) -> Vec<TokenId> {
+        self.id_to_token
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| std::str::from_utf8(token).is_err())
+            .map(|(id, _)| id as TokenId)
+            .collect()
+    }
+
     // SentencePiece implementation after https://guillaume-be.github.io/2020-05-30/sentence_piece
     /// Tokenize a `text` with this tokenizer.
     ///
@@ -80,12 +314,140 @@ impl EmbeddedTokenizer {
         text: &str,
         bos: bool,
     ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        let ids = self.tokenize_ids(text, bos)?;
+        Ok(self.pairs(ids, bos))
+    }
+
+    /// Tokenize a `text` with this tokenizer, controlling whether special tokens are recognized.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted.
+    /// If `split_special` is true, the text is first split on the special tokens set with
+    /// [Self::set_special_token_ids], which become single tokens; the text between them is
+    /// tokenized as usual, except that only the start of the input gets the dummy prefix.
+    ///
+    /// Fails with [TokenizationError::InvalidTokenId] if a special token ID is not in the
+    /// vocabulary.
+    pub(crate) fn tokenize_with_special(
+        &self,
+        text: &str,
+        bos: bool,
+        split_special: bool,
+    ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        if !split_special || self.special_token_ids.is_empty() {
+            return self.tokenize(text, bos);
+        }
+
+        let mut special_tokens = Vec::with_capacity(self.special_token_ids.len());
+        for &id in &self.special_token_ids {
+            let token = self
+                .id_to_token
+                .get(id as usize)
+                .ok_or(TokenizationError::InvalidTokenId(id))?;
+            if let Some(token) = std::str::from_utf8(token).ok().filter(|t| !t.is_empty()) {
+                special_tokens.push((token, id));
+            }
+        }
+
+        self.check_input(text)?;
+        let mut ids = if bos { vec![1] } else { vec![] };
+        let mut rest = text;
+        // Only the start of the input gets the dummy prefix, not the text after each
+        // special token.
+        let mut add_dummy_prefix = self.add_dummy_prefix;
+        loop {
+            // Split on the first special token, preferring the longest one at that position.
+            let next = special_tokens
+                .iter()
+                .filter_map(|&(token, id)| Some((rest.find(token)?, token.len(), id)))
+                .min_by_key(|&(start, len, _)| (start, Reverse(len)));
+            let Some((start, len, id)) = next else {
+                ids.extend(self.tokenize_segment(rest, false, add_dummy_prefix)?);
+                break;
+            };
+            ids.extend(self.tokenize_segment(&rest[..start], false, add_dummy_prefix)?);
+            ids.push(id);
+            rest = &rest[start + len..];
+            add_dummy_prefix = false;
+        }
+        Ok(self.pairs(ids, bos))
+    }
+
+    /// Pairs each of the tokenized `ids` with its bytes.
+    fn pairs(&self, ids: Vec<TokenId>, bos: bool) -> Vec<(Vec<u8>, TokenId)> {
+        ids.into_iter()
+            .enumerate()
+            .map(|(i, id)| {
+                // The beginning-of-string token is not part of the text.
+                let token = if bos && i == 0 {
+                    vec![]
+                } else {
+                    self.id_to_token[id as usize].clone()
+                };
+                (token, id)
+            })
+            .collect()
+    }
+
+    /// Tokenize a `text` with this tokenizer, returning only the token IDs.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted.
+    pub(crate) fn tokenize_ids(
+        &self,
+        text: &str,
+        bos: bool,
+    ) -> Result<Vec<TokenId>, TokenizationError> {
+        self.check_input(text)?;
+        self.tokenize_segment(text, bos, self.add_dummy_prefix)
+    }
+
+    /// Checks that the tokenizer can tokenize `text`.
+    fn check_input(&self, text: &str) -> Result<(), TokenizationError> {
+        if self.decode_only {
+            return Err(TokenizationError::TokenizationFailed {
+                error: Box::new(EmbeddedTokenizerError::DecodeOnly),
+            });
+        }
+        if let Some(max_input_bytes) = self.max_input_bytes.filter(|&max| text.len() > max) {
+            return Err(TokenizationError::TokenizationFailed {
+                error: Box::new(EmbeddedTokenizerError::InputTooLong {
+                    len: text.len(),
+                    max_input_bytes,
+                }),
+            });
+        }
+        Ok(())
+    }
+
+    /// Tokenize a `text` that has been checked with [Self::check_input], adding the dummy
+    /// prefix only if `add_dummy_prefix` is true.
+    fn tokenize_segment(
+        &self,
+        text: &str,
+        bos: bool,
+        add_dummy_prefix: bool,
+    ) -> Result<Vec<TokenId>, TokenizationError> {
+        let text = if add_dummy_prefix {
+            self.normalize(text)
+        } else {
+            Cow::Borrowed(text)
+        };
         let len = text.len();
 
         let mut score = vec![0usize; len + 1];
-        let mut prev = vec![TokenId::default(); len + 1];
+        // The token ending at each position, and the number of bytes it covers.
+        let mut prev: Vec<Option<(TokenId, usize)>> = vec![None; len + 1];
+
+        for i in 0..=len {
+            // All the tokens ending at `i` have been seen, so if none of them cover it,
+            // fall back to covering the preceding character with the unknown token.
+            if i > 0 && prev[i].is_none() && text.is_char_boundary(i) {
+                if let Some(unk_token_id) = self.unk_token_id {
+                    let start = (0..i).rev().find(|&j| text.is_char_boundary(j)).unwrap();
+                    score[i] = score[start];
+                    prev[i] = Some((unk_token_id, i - start));
+                }
+            }
 
-        for i in 0..len {
             let max_len = (len - i).min(self.max_token_length);
             for sub_len in 1..=max_len {
                 let sub = &text.as_bytes()[i..i + sub_len];
@@ -98,7 +460,7 @@ impl EmbeddedTokenizer {
 
                     if score[next] < local_score {
                         score[next] = local_score;
-                        prev[next] = *token;
+                        prev[next] = Some((*token, sub_len));
                     }
                 }
             }
@@ -108,23 +470,31 @@ impl EmbeddedTokenizer {
         let mut res = vec![];
         let mut i = len;
         while i > 0 {
-            let token_id = prev[i];
-            if token_id == 0 {
+            let Some((token_id, token_len)) = prev[i] else {
                 return Err(TokenizationError::TokenizationFailed {
                     error: Box::new(EmbeddedTokenizerError::Arbitrary(
                         "the backward pass for the tokenizer encountered a non-set token"
                             .to_string(),
                     )),
                 });
-            }
-            let token = self.id_to_token[token_id as usize].as_slice();
-            res.push((token.to_vec(), token_id));
-            i -= token.len();
+            };
+            // A token cannot cover more bytes than precede it; if one does, the forward
+            // pass is broken, so report it instead of underflowing.
+            let Some(start) = i.checked_sub(token_len).filter(|_| token_len > 0) else {
+                return Err(TokenizationError::TokenizationFailed {
+                    error: Box::new(EmbeddedTokenizerError::Arbitrary(format!(
+                        "the backward pass for the tokenizer found token {token_id} covering \
+                         {token_len} bytes at byte {i} of {len}"
+                    ))),
+                });
+            };
+            res.push(token_id);
+            i = start;
         }
 
         if bos {
             // TODO: replace with vocab.bos
-            res.push((vec![], 1));
+            res.push(1);
         }
 
         // Pieces are in reverse order so correct that
@@ -142,12 +512,162 @@ impl EmbeddedTokenizer {
                 continue;
             }
 
-            vec.append(&mut self.id_to_token[token as usize].to_vec());
+            vec.extend_from_slice(&self.id_to_token[token as usize]);
         }
 
         vec
     }
 
+    /// Returns the text of `id` if it is a special token, for showing it while debugging.
+    ///
+    /// The beginning-of-string token, the unknown token and the tokens set with
+    /// [Self::set_special_token_ids] are special. The surrounding whitespace of their text
+    /// is removed; if nothing else is left, the ID is shown instead.
+    pub(crate) fn special_token_text(&self, id: TokenId) -> Option<String> {
+        let is_special =
+            id == 1 || Some(id) == self.unk_token_id || self.special_token_ids.contains(&id);
+        if !is_special {
+            return None;
+        }
+
+        let text = String::from_utf8_lossy(self.token_bytes(id as usize)?);
+        Some(match text.trim() {
+            "" => format!("<token:{id}>"),
+            text => text.to_string(),
+        })
+    }
+
+    /// Decode a list `tokens` with this tokenizer, restoring the spaces between words.
+    ///
+    /// The SentencePiece metasymbol (`▁`) is converted to a space, except at the very
+    /// beginning of the text. If a dummy prefix is added when tokenizing, the leading space
+    /// that it introduced is removed.
+    pub(crate) fn decode_normalized(
+        &self,
+        tokens: Vec<TokenId>,
+        skip_special_tokens: bool,
+    ) -> Vec<u8> {
+        let decoded = self.decode(tokens, skip_special_tokens);
+
+        let mut vec = Vec::with_capacity(decoded.len());
+        let mut rest = decoded.as_slice();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(SENTENCEPIECE_SPACE) {
+                if !vec.is_empty() {
+                    vec.push(b' ');
+                }
+                rest = after;
+            } else {
+                vec.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+
+        if self.add_dummy_prefix && vec.first() == Some(&b' ') {
+            vec.remove(0);
+        }
+
+        vec
+    }
+
+    /// Decode a list `tokens` with this tokenizer, returning the range of the output
+    /// that each token produced.
+    pub(crate) fn decode_with_spans(
+        &self,
+        tokens: &[TokenId],
+        skip_special_tokens: bool,
+    ) -> (Vec<u8>, Vec<Range<usize>>) {
+        let mut vec = vec![];
+        let mut spans = Vec::with_capacity(tokens.len());
+
+        for &token in tokens {
+            let start = vec.len();
+            if !(skip_special_tokens && token == 1) {
+                vec.extend_from_slice(&self.id_to_token[token as usize]);
+            }
+            spans.push(start..vec.len());
+        }
+
+        (vec, spans)
+    }
+
+    /// Exports this tokenizer as a Hugging Face `tokenizer.json` with a Unigram model.
+    ///
+    /// Each token is exported with its score, with spaces replaced by the SentencePiece
+    /// metasymbol (`▁`). Single bytes that are not valid UTF-8 are exported as byte-fallback
+    /// tokens (e.g. `<0x80>`); other tokens that are not valid UTF-8 cannot be represented,
+    /// and fail with [EmbeddedTokenizerError::NonUtf8Token]. The beginning-of-string and
+    /// unknown tokens are exported as special tokens, and the beginning-of-string token is
+    /// inserted when tokenizing with special tokens.
+    pub fn to_huggingface_json(&self) -> Result<String, EmbeddedTokenizerError> {
+        let metasymbol = std::str::from_utf8(SENTENCEPIECE_SPACE).unwrap();
+        let export_error = |error| EmbeddedTokenizerError::Export(error);
+
+        let mut byte_fallback = false;
+        let vocab = self
+            .id_to_token
+            .iter()
+            .zip(&self.id_to_token_score)
+            .enumerate()
+            .map(|(id, (token, &score))| {
+                let token = match (std::str::from_utf8(token), token.as_slice()) {
+                    (Ok(token), _) => token.replace(' ', metasymbol),
+                    (Err(_), &[byte]) => {
+                        byte_fallback = true;
+                        format!("<0x{byte:02X}>")
+                    }
+                    (Err(_), _) => {
+                        return Err(EmbeddedTokenizerError::NonUtf8Token { id: id as TokenId })
+                    }
+                };
+                Ok((token, score as f64))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let special_tokens: Vec<_> = [Some(1), self.unk_token_id]
+            .into_iter()
+            .flatten()
+            .filter_map(|id| vocab.get(id as usize))
+            .map(|(token, _)| AddedToken::from(token.clone(), true))
+            .collect();
+        let bos_token = vocab.get(1).map(|(token, _)| token.clone());
+        let unk_token_id = self.unk_token_id.map(|id| id as usize);
+
+        let mut tokenizer = tokenizers::Tokenizer::new(
+            Unigram::from(vocab, unk_token_id, byte_fallback).map_err(export_error)?,
+        );
+
+        let mut normalizers = vec![];
+        if self.add_dummy_prefix {
+            normalizers.push(Prepend::new(metasymbol.to_string()).into());
+        }
+        normalizers.push(Replace::new(" ", metasymbol).map_err(export_error)?.into());
+        tokenizer.with_normalizer(normalizers::Sequence::new(normalizers));
+
+        let mut decoders = vec![
+            Replace::new(metasymbol, " ").map_err(export_error)?.into(),
+            ByteFallback::new().into(),
+            Fuse::new().into(),
+        ];
+        if self.add_dummy_prefix {
+            decoders.push(Strip::new(' ', 1, 0).into());
+        }
+        tokenizer.with_decoder(Sequence::new(decoders));
+
+        if let Some(bos_token) = bos_token {
+            let post_processor = TemplateProcessing::builder()
+                .try_single(vec![bos_token.as_str(), "$A"])
+                .map_err(|error| export_error(error.into()))?
+                .special_tokens(vec![(bos_token.as_str(), 1)])
+                .build()
+                .map_err(|error| export_error(error.into()))?;
+            tokenizer.with_post_processor(post_processor);
+        }
+        tokenizer.add_special_tokens(&special_tokens);
+
+        tokenizer.to_string(false).map_err(export_error)
+    }
+
     pub(crate) fn iter(&self) -> impl Iterator<Item = (Token, f32)> + '_ {
         self.id_to_token
             .iter()
@@ -155,3 +675,383 @@ impl EmbeddedTokenizer {
             .map(|(token, score)| (token.clone(), *score))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HuggingFaceTokenizer, Tokenizer};
+    use std::str::FromStr;
+
+    fn tokenizer(tokens: &[&str]) -> EmbeddedTokenizer {
+        let mut tokenizer = EmbeddedTokenizer::default();
+        for (id, token) in tokens.iter().enumerate() {
+            tokenizer.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+        }
+        tokenizer
+    }
+
+    fn token_ids(tokenizer: &EmbeddedTokenizer, text: &str) -> Vec<TokenId> {
+        tokenizer
+            .tokenize(text, false)
+            .unwrap()
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    #[test]
+    fn test_add_dummy_prefix() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "Hello", " Hello", " world"]);
+        assert_eq!(token_ids(&tokenizer, "Hello world"), vec![3, 5]);
+
+        assert_eq!(tokenizer.normalize("Hello world"), "Hello world");
+
+        tokenizer.set_add_dummy_prefix(true);
+        assert_eq!(token_ids(&tokenizer, "Hello world"), vec![4, 5]);
+        assert_eq!(tokenizer.normalize("Hello world"), " Hello world");
+        assert_eq!(tokenizer.normalize(""), "");
+        assert_eq!(token_ids(&tokenizer, ""), Vec::<TokenId>::new());
+    }
+
+    #[test]
+    fn test_tokenize_ids() {
+        let tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "Hello", " world"]);
+        assert_eq!(
+            tokenizer.tokenize_ids("Hello world", true).unwrap(),
+            vec![1, 3, 4]
+        );
+        assert_eq!(
+            tokenizer.tokenize("Hello world", true).unwrap(),
+            vec![(vec![], 1), (b"Hello".to_vec(), 3), (b" world".to_vec(), 4)]
+        );
+
+        let tokenizer = Tokenizer::from(tokenizer);
+        assert_eq!(
+            tokenizer.ids_to_pairs(&[1, 3, 4]),
+            vec![
+                (b"<s>".to_vec(), 1),
+                (b"Hello".to_vec(), 3),
+                (b" world".to_vec(), 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_random_vocabularies() {
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let alphabet = ["a", "b", " ", "é", "\u{2581}"];
+        let random_text = |rng: &mut StdRng, max_len: usize| -> String {
+            let len = rng.gen_range(0..=max_len);
+            (0..len).map(|_| *alphabet.choose(rng).unwrap()).collect()
+        };
+
+        for _ in 0..200 {
+            let mut tokenizer = EmbeddedTokenizer::default();
+            tokenizer.push_token(0, b"<unk>".to_vec(), 0.0);
+            for id in 1..rng.gen_range(2..20) {
+                let token = random_text(&mut rng, 4);
+                tokenizer.push_token(id, token.into_bytes(), rng.gen_range(-10.0..0.0));
+            }
+            tokenizer.set_unk_token_id(rng.gen_bool(0.5).then_some(0));
+            tokenizer.set_add_dummy_prefix(rng.gen_bool(0.5));
+
+            for _ in 0..20 {
+                let text = random_text(&mut rng, 16);
+                let Ok(pairs) = tokenizer.tokenize(&text, false) else {
+                    continue;
+                };
+                // Without an unknown token, every byte of the text is covered by its token.
+                if !pairs.iter().any(|&(_, id)| id == 0) {
+                    let expected = if tokenizer.add_dummy_prefix && !text.is_empty() {
+                        format!(" {text}")
+                    } else {
+                        text.clone()
+                    };
+                    let decoded: Vec<u8> = pairs.into_iter().flat_map(|(t, _)| t).collect();
+                    assert_eq!(decoded, expected.as_bytes());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_special_token_text() {
+        let mut tokenizer = tokenizer(&["<unk>", "", "\n</s>\n", "<|im_start|>", "hi"]);
+        tokenizer.set_unk_token_id(Some(0));
+        tokenizer.set_special_token_ids(vec![2, 3]);
+
+        assert_eq!(tokenizer.special_token_text(0).as_deref(), Some("<unk>"));
+        assert_eq!(
+            tokenizer.special_token_text(1).as_deref(),
+            Some("<token:1>")
+        );
+        assert_eq!(tokenizer.special_token_text(2).as_deref(), Some("</s>"));
+        assert_eq!(
+            tokenizer.special_token_text(3).as_deref(),
+            Some("<|im_start|>")
+        );
+        assert_eq!(tokenizer.special_token_text(4), None);
+
+        let tokenizer = Tokenizer::from(tokenizer);
+        assert_eq!(
+            tokenizer.decode_debug(&[1, 3, 4, 4, 2, 9]),
+            "<token:1><|im_start|>hihi</s><invalid:9>"
+        );
+    }
+
+    #[test]
+    fn test_token_bytes() {
+        let tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "Hello"]);
+        assert_eq!(tokenizer.token_bytes(3), Some(&b"Hello"[..]));
+        assert_eq!(tokenizer.token_bytes(4), None);
+    }
+
+    #[test]
+    fn test_tokenize_with_special() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "ba</s", ">", "b", "a"]);
+        let ids = |tokenizer: &EmbeddedTokenizer, split_special| -> Vec<TokenId> {
+            tokenizer
+                .tokenize_with_special("ba</s>", true, split_special)
+                .unwrap()
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect()
+        };
+
+        // Without special tokens, the control string can be merged with the text around it.
+        assert_eq!(ids(&tokenizer, true), vec![1, 3, 4]);
+
+        tokenizer.set_special_token_ids(vec![2]);
+        assert_eq!(ids(&tokenizer, true), vec![1, 5, 6, 2]);
+        assert_eq!(ids(&tokenizer, false), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_tokenize_with_invalid_special_token_id() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "a"]);
+        tokenizer.set_special_token_ids(vec![2, 7]);
+
+        assert!(matches!(
+            tokenizer.tokenize_with_special("a</s>", false, true),
+            Err(TokenizationError::InvalidTokenId(7))
+        ));
+        assert!(tokenizer
+            .tokenize_with_special("a</s>", false, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_tokenize_with_special_and_dummy_prefix() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "Hello", " Hello", " "]);
+        tokenizer.set_add_dummy_prefix(true);
+        tokenizer.set_special_token_ids(vec![2]);
+        let ids = |text| -> Vec<TokenId> {
+            tokenizer
+                .tokenize_with_special(text, false, true)
+                .unwrap()
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect()
+        };
+
+        // Only the start of the input gets the dummy prefix.
+        assert_eq!(ids("Hello</s>Hello"), vec![4, 2, 3]);
+        assert_eq!(ids("</s>Hello"), vec![2, 3]);
+        assert_eq!(ids("</s> Hello"), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_longest_prefix_token() {
+        let tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "H", "Hel", "Hello", " world"]);
+        assert_eq!(tokenizer.longest_prefix_token(b"Hello world"), Some((5, 5)));
+        assert_eq!(tokenizer.longest_prefix_token(b"Help"), Some((4, 3)));
+        assert_eq!(tokenizer.longest_prefix_token(b"world"), None);
+        assert_eq!(tokenizer.longest_prefix_token(b""), None);
+    }
+
+    #[test]
+    fn test_non_utf8_token_ids() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "€"]);
+        tokenizer.push_token(4, vec![0xE2, 0x82], 0.0);
+        tokenizer.push_token(5, vec![0xAC], 0.0);
+        assert_eq!(tokenizer.non_utf8_token_ids(), vec![4, 5]);
+    }
+
+    #[test]
+    fn test_unk_token_id() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "a", "b", "ab"]);
+        assert!(matches!(
+            tokenizer.tokenize("a€b", false),
+            Err(TokenizationError::TokenizationFailed { .. })
+        ));
+
+        tokenizer.set_unk_token_id(Some(0));
+        assert_eq!(token_ids(&tokenizer, "a€b"), vec![3, 0, 4]);
+        assert_eq!(token_ids(&tokenizer, "€€"), vec![0, 0]);
+        assert_eq!(token_ids(&tokenizer, "ab"), vec![5]);
+    }
+
+    #[test]
+    fn test_decode_normalized() {
+        let sentencepiece = tokenizer(&["<unk>", "<s>", "</s>", "▁Hello", "▁wor", "ld", "!"]);
+        assert_eq!(
+            sentencepiece.decode_normalized(vec![1, 3, 4, 5, 6], true),
+            b"Hello world!"
+        );
+
+        // GGML vocabularies store the metasymbol as a regular space.
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", " Hello", " world"]);
+        assert_eq!(
+            tokenizer.decode_normalized(vec![3, 4], true),
+            b" Hello world"
+        );
+        tokenizer.set_add_dummy_prefix(true);
+        assert_eq!(
+            tokenizer.decode_normalized(vec![3, 4], true),
+            b"Hello world"
+        );
+    }
+
+    #[test]
+    fn test_id_normalized() {
+        let mut sentencepiece = tokenizer(&["<unk>", "<s>", "</s>", "Hello", "▁hello"]);
+        assert_eq!(sentencepiece.id_normalized("Hello"), Some(3));
+        assert_eq!(sentencepiece.id_normalized(" hello"), Some(4));
+        assert_eq!(sentencepiece.id_normalized("hello"), None);
+
+        sentencepiece.set_add_dummy_prefix(true);
+        assert_eq!(sentencepiece.id_normalized("hello"), Some(4));
+
+        // GGML vocabularies store the metasymbol as a regular space.
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", " hello"]);
+        tokenizer.set_add_dummy_prefix(true);
+        assert_eq!(tokenizer.id_normalized("hello"), Some(3));
+    }
+
+    #[test]
+    fn test_decode_only() {
+        let mut tokenizer = EmbeddedTokenizer::new_decode_only();
+        for (id, token) in ["<unk>", "<s>", "</s>", "Hello"].iter().enumerate() {
+            tokenizer.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+        }
+
+        assert!(tokenizer.token_to_id.is_empty());
+        assert_eq!(tokenizer.id(b"Hello"), None);
+        assert_eq!(tokenizer.decode(vec![1, 3], true), b"Hello");
+        assert!(matches!(
+            tokenizer.tokenize("Hello", false),
+            Err(TokenizationError::TokenizationFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_max_input_bytes() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "a"]);
+        tokenizer.set_max_input_bytes(Some(3));
+        assert_eq!(token_ids(&tokenizer, "aaa"), vec![3, 3, 3]);
+
+        let Err(TokenizationError::TokenizationFailed { error }) =
+            tokenizer.tokenize("aaaa", false)
+        else {
+            panic!("expected tokenization to fail");
+        };
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(EmbeddedTokenizerError::InputTooLong {
+                len: 4,
+                max_input_bytes: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_to_huggingface_json() {
+        let mut embedded = EmbeddedTokenizer::default();
+        for (id, token) in ["<unk>", "<s>", "</s>", " Hello", " world", "!"]
+            .iter()
+            .enumerate()
+        {
+            embedded.push_token(id as TokenId, token.as_bytes().to_vec(), -(id as f32));
+        }
+        embedded.push_token(6, vec![0x80], -6.0);
+        embedded.set_add_dummy_prefix(true);
+        embedded.set_unk_token_id(Some(0));
+
+        let json = embedded.to_huggingface_json().unwrap();
+        let huggingface =
+            HuggingFaceTokenizer::new(tokenizers::Tokenizer::from_str(&json).unwrap());
+        assert_eq!(huggingface.id(b"<0x80>"), Some(6));
+
+        let ids = embedded.tokenize_ids("Hello world!", true).unwrap();
+        assert_eq!(ids, vec![1, 3, 4, 5]);
+        assert_eq!(huggingface.tokenize_ids("Hello world!", true).unwrap(), ids);
+        assert_eq!(huggingface.decode(ids, true), b"Hello world!");
+
+        embedded.push_token(7, vec![0xE2, 0x82], -7.0);
+        assert!(matches!(
+            embedded.to_huggingface_json(),
+            Err(EmbeddedTokenizerError::NonUtf8Token { id: 7 })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "a", "a"]);
+        assert!(tokenizer.validate().is_ok());
+
+        tokenizer.token_to_id.insert(b"b".to_vec(), 3);
+        assert!(matches!(
+            tokenizer.validate(),
+            Err(EmbeddedTokenizerError::Inconsistent(_))
+        ));
+
+        tokenizer.token_to_id.remove(b"b".as_slice());
+        tokenizer.token_to_id.remove(b"a".as_slice());
+        assert!(matches!(
+            tokenizer.validate(),
+            Err(EmbeddedTokenizerError::Inconsistent(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_special_tokens() {
+        let mut tokenizer = tokenizer(&["<unk>", "<s>", "</s>"]);
+        assert!(tokenizer.validate_special_tokens().is_ok());
+
+        tokenizer.set_unk_token_id(Some(3));
+        assert!(matches!(
+            tokenizer.validate_special_tokens(),
+            Err(EmbeddedTokenizerError::InvalidSpecialTokenId {
+                name: "unknown",
+                id: 3,
+                len: 3
+            })
+        ));
+
+        tokenizer.set_unk_token_id(Some(0));
+        tokenizer.set_special_token_ids(vec![2, 5]);
+        assert!(matches!(
+            tokenizer.validate_special_tokens(),
+            Err(EmbeddedTokenizerError::InvalidSpecialTokenId {
+                name: "special",
+                id: 5,
+                len: 3
+            })
+        ));
+
+        assert!(matches!(
+            EmbeddedTokenizer::default().validate_special_tokens(),
+            Err(EmbeddedTokenizerError::InvalidSpecialTokenId { id: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_with_spans() {
+        let tokenizer = tokenizer(&["<unk>", "<s>", "</s>", "Hello", " world"]);
+        let (text, spans) = tokenizer.decode_with_spans(&[1, 3, 4], true);
+        assert_eq!(text, b"Hello world");
+        assert_eq!(spans, vec![0..0, 0..5, 5..11]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{borrow::Cow, ops::Range, sync::Arc};
+
+use super::{PromptFormat, TokenId, TokenizationError, Tokenizer};
+
+/// A cheap, cloneable and thread-safe handle to a [Tokenizer].
+///
+/// Clones share the same tokenizer, so a handle can be given to every component that
+/// needs to tokenize or decode text without copying the vocabulary. Only the read-only
+/// methods of [Tokenizer] are available through the handle.
+#[derive(Clone)]
+pub struct TokenizerHandle(Arc<Tokenizer>);
+impl TokenizerHandle {
+    /// Create a new handle that owns `tokenizer`.
+    pub fn new(tokenizer: Tokenizer) -> Self {
+        Self(Arc::new(tokenizer))
+    }
+
+    /// Converts a token to the token ID it represents in this tokenizer.
+    pub fn id(&self, token: &[u8]) -> Option<TokenId> {
+        self.0.id(token)
+    }
+
+    /// Converts a word to the token ID it represents in this tokenizer, applying the same
+    /// normalization as tokenization.
+    ///
+    /// See [Tokenizer::id_normalized].
+    pub fn id_normalized(&self, text: &str) -> Option<TokenId> {
+        self.0.id_normalized(text)
+    }
+
+    /// Returns the text that tokenization operates on, after normalization.
+    ///
+    /// See [Tokenizer::normalize].
+    pub fn normalize(&self, text: &str) -> String {
+        self.0.normalize(text)
+    }
+
+    /// Converts a token index to the token it represents in this tokenizer.
+    pub fn token(&self, idx: usize) -> Vec<u8> {
+        self.0.token(idx)
+    }
+
+    /// Converts a token index to the token it represents in this tokenizer, or `None` if
+    /// there is no such token.
+    ///
+    /// See [Tokenizer::token_ref].
+    pub fn token_ref(&self, idx: usize) -> Option<Cow<'_, [u8]>> {
+        self.0.token_ref(idx)
+    }
+
+    /// Returns the number of tokens in the tokenizer.
+    ///
+    /// See [Tokenizer::len].
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the number of tokens in the tokenizer, including tokens added on top of
+    /// the base vocabulary.
+    ///
+    /// See [Tokenizer::vocab_size_with_added].
+    pub fn vocab_size_with_added(&self) -> usize {
+        self.0.vocab_size_with_added()
+    }
+
+    /// Guesses the chat format that the model expects from the special tokens in this tokenizer.
+    ///
+    /// See [Tokenizer::detect_prompt_format].
+    pub fn detect_prompt_format(&self) -> Option<PromptFormat> {
+        self.0.detect_prompt_format()
+    }
+
+    /// Builds any state of the tokenizer that is otherwise built on first use.
+    ///
+    /// See [Tokenizer::prepare].
+    pub fn prepare(&self) {
+        self.0.prepare()
+    }
+
+    /// Returns whether the tokenizer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the length in bytes of the longest token in the tokenizer.
+    pub fn max_token_byte_length(&self) -> usize {
+        self.0.max_token_byte_length()
+    }
+
+    /// Returns the longest token in the vocabulary that `text` starts with, and its length
+    /// in bytes.
+    ///
+    /// See [Tokenizer::longest_prefix_token].
+    pub fn longest_prefix_token(&self, text: &[u8]) -> Option<(TokenId, usize)> {
+        self.0.longest_prefix_token(text)
+    }
+
+    /// Returns the IDs of the tokens whose bytes are not valid UTF-8 by themselves.
+    pub fn non_utf8_token_ids(&self) -> Vec<TokenId> {
+        self.0.non_utf8_token_ids()
+    }
+
+    /// Tokenize a `text` with this tokenizer.
+    ///
+    /// See [Tokenizer::tokenize].
+    pub fn tokenize(
+        &self,
+        text: &str,
+        bos: bool,
+    ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        self.0.tokenize(text, bos)
+    }
+
+    /// Tokenize a `text` with this tokenizer, returning only the token IDs.
+    ///
+    /// See [Tokenizer::tokenize_ids].
+    pub fn tokenize_ids(&self, text: &str, bos: bool) -> Result<Vec<TokenId>, TokenizationError> {
+        self.0.tokenize_ids(text, bos)
+    }
+
+    /// Pairs each of `ids` with the bytes of the token it represents.
+    ///
+    /// See [Tokenizer::ids_to_pairs].
+    pub fn ids_to_pairs(&self, ids: &[TokenId]) -> Vec<(Vec<u8>, TokenId)> {
+        self.0.ids_to_pairs(ids)
+    }
+
+    /// Tokenize a `text` with this tokenizer, controlling whether special tokens are recognized.
+    ///
+    /// See [Tokenizer::tokenize_with_special].
+    pub fn tokenize_with_special(
+        &self,
+        text: &str,
+        bos: bool,
+        split_special: bool,
+    ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        self.0.tokenize_with_special(text, bos, split_special)
+    }
+
+    /// Decode a list `tokens` with this tokenizer.
+    pub fn decode(&self, tokens: Vec<TokenId>, bos: bool) -> Vec<u8> {
+        self.0.decode(tokens, bos)
+    }
+
+    /// Decode a list `tokens` with this tokenizer into text with the spaces between words
+    /// restored.
+    ///
+    /// See [Tokenizer::decode_normalized].
+    pub fn decode_normalized(&self, tokens: Vec<TokenId>, bos: bool) -> Vec<u8> {
+        self.0.decode_normalized(tokens, bos)
+    }
+
+    /// Decode a list `tokens` into text for debugging, showing where the special tokens are.
+    ///
+    /// See [Tokenizer::decode_debug].
+    pub fn decode_debug(&self, tokens: &[TokenId]) -> String {
+        self.0.decode_debug(tokens)
+    }
+
+    /// Decode a list `tokens` with this tokenizer, also returning the range of bytes in the
+    /// output that each token produced.
+    ///
+    /// See [Tokenizer::decode_with_spans].
+    pub fn decode_with_spans(
+        &self,
+        tokens: &[TokenId],
+        skip_special_tokens: bool,
+    ) -> (Vec<u8>, Vec<Range<usize>>) {
+        self.0.decode_with_spans(tokens, skip_special_tokens)
+    }
+
+    /// Decode `ids` as they are produced, yielding the text of each token once it is complete.
+    ///
+    /// See [Tokenizer::decode_iter].
+    pub fn decode_iter<'a>(
+        &'a self,
+        ids: impl Iterator<Item = TokenId> + 'a,
+    ) -> impl Iterator<Item = String> + 'a {
+        self.0.decode_iter(ids)
+    }
+}
+impl From<Tokenizer> for TokenizerHandle {
+    fn from(tokenizer: Tokenizer) -> Self {
+        Self::new(tokenizer)
+    }
+}
+impl From<Arc<Tokenizer>> for TokenizerHandle {
+    fn from(tokenizer: Arc<Tokenizer>) -> Self {
+        Self(tokenizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedTokenizer;
+
+    #[test]
+    fn test_clones_share_tokenizer() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<TokenizerHandle>();
+
+        let mut tokenizer = EmbeddedTokenizer::default();
+        for (id, token) in ["<unk>", "<s>", "</s>", "Hello"].iter().enumerate() {
+            tokenizer.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+        }
+        let handle = TokenizerHandle::new(tokenizer.into());
+        let clone = handle.clone();
+
+        assert!(Arc::ptr_eq(&handle.0, &clone.0));
+        assert_eq!(clone.id(b"Hello"), Some(3));
+        assert_eq!(clone.decode(vec![3], true), b"Hello");
+    }
+}
@@ -1,22 +1,171 @@
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
 use super::{TokenId, TokenizationError};
 
 /// A Hugging Face tokenizer.
 #[derive(Debug, Clone)]
 pub struct HuggingFaceTokenizer {
     pub(crate) tokenizer: tokenizers::Tokenizer,
+
+    /// The length in bytes of the longest token in the vocabulary.
+    max_token_length: usize,
+
+    /// Whether to remove the spaces before punctuation and contractions after decoding.
+    clean_up_tokenization_spaces: bool,
+
+    /// Whether to replace the SentencePiece metasymbol (`▁`) with a space in the bytes
+    /// of tokenized tokens.
+    replace_metaspace_in_tokens: bool,
+
+    /// The results of recent tokenizations, if caching is enabled.
+    tokenize_cache: TokenizeCache,
+
+    /// A copy of the tokenizer without its special added tokens, built on first use.
+    literal_tokenizer: LiteralTokenizer,
 }
 
 impl HuggingFaceTokenizer {
     /// Create a new `HuggingFaceTokenizer`.
     pub fn new(tokenizer: tokenizers::Tokenizer) -> Self {
-        Self { tokenizer }
+        let max_token_length = tokenizer
+            .get_vocab(true)
+            .keys()
+            .map(|token| token.len())
+            .max()
+            .unwrap_or_default();
+
+        Self {
+            tokenizer,
+            max_token_length,
+            clean_up_tokenization_spaces: false,
+            replace_metaspace_in_tokens: false,
+            tokenize_cache: TokenizeCache::default(),
+            literal_tokenizer: LiteralTokenizer::default(),
+        }
+    }
+
+    /// Sets whether decoding should clean up the spaces before punctuation and contractions
+    /// (e.g. `"Hello , world"` becomes `"Hello, world"`).
+    ///
+    /// This mirrors the `clean_up_tokenization_spaces` option of Hugging Face `transformers`,
+    /// which is stored outside of `tokenizer.json` and is therefore disabled by default.
+    pub fn set_clean_up_tokenization_spaces(&mut self, clean_up_tokenization_spaces: bool) {
+        self.clean_up_tokenization_spaces = clean_up_tokenization_spaces;
+    }
+
+    /// Sets whether the bytes of each token returned by tokenization should have the
+    /// SentencePiece metasymbol (`▁`) replaced with a space.
+    ///
+    /// Tokenization returns each token as it is stored in the vocabulary, so tokenizers
+    /// using the Metaspace pre-tokenizer produce `"▁hello"` rather than `" hello"`. Enable
+    /// this to compare token bytes with those of the embedded tokenizer, which stores spaces.
+    /// This only affects the returned bytes; token IDs and decoding are unchanged. Other
+    /// encodings, such as ByteLevel's `Ġ`, are not converted. This is disabled by default.
+    pub fn set_replace_metaspace_in_tokens(&mut self, replace_metaspace_in_tokens: bool) {
+        self.replace_metaspace_in_tokens = replace_metaspace_in_tokens;
+        self.tokenize_cache.clear();
+    }
+
+    /// Sets the number of tokenization results to cache, keyed by the text and `bos` flag.
+    ///
+    /// When the same texts (such as system prompts) are tokenized repeatedly, the cached
+    /// tokens are returned instead of tokenizing the text again. The least recently used
+    /// result is evicted when the cache is full. The cache is guarded by a mutex, so it can
+    /// be used by a tokenizer shared between threads. It is intended for a small number of
+    /// texts, and is disabled (a capacity of 0) by default.
+    pub fn set_tokenize_cache_capacity(&mut self, capacity: usize) {
+        self.tokenize_cache = TokenizeCache::new(capacity);
+    }
+
+    /// Sets whether the `tokenizers` library may use its internal thread pool, for every
+    /// Hugging Face tokenizer in the process.
+    ///
+    /// Disable this when requests are already tokenized in parallel, so that the library's
+    /// threads do not compete with them. The library reads this setting from the
+    /// `TOKENIZERS_PARALLELISM` environment variable, so it is process-global, and should
+    /// be set before tokenizing on other threads.
+    pub fn set_parallelism(enabled: bool) {
+        std::env::set_var(
+            tokenizers::utils::parallelism::ENV_VARIABLE,
+            parallelism_env_value(enabled),
+        );
+    }
+
+    /// Adds `tokens` to the vocabulary as special tokens, returning how many were added.
+    ///
+    /// Tokens that are already in the vocabulary are not added again. Special tokens are
+    /// always tokenized as themselves, and are skipped when decoding with
+    /// `skip_special_tokens`.
+    ///
+    /// This modifies the tokenizer, so it needs exclusive access: add the tokens before
+    /// sharing the tokenizer with a [TokenizerHandle](crate::TokenizerHandle).
+    pub fn add_special_tokens(&mut self, tokens: &[&str]) -> usize {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|token| tokenizers::AddedToken::from(*token, true))
+            .collect();
+        let added = self.tokenizer.add_special_tokens(&tokens);
+        self.tokens_added(&tokens);
+        added
+    }
+
+    /// Adds `tokens` to the vocabulary, returning how many were added.
+    ///
+    /// Tokens that are already in the vocabulary are not added again. See
+    /// [Self::add_special_tokens] for sharing the tokenizer afterwards.
+    pub fn add_tokens(&mut self, tokens: &[&str]) -> usize {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|token| tokenizers::AddedToken::from(*token, false))
+            .collect();
+        let added = self.tokenizer.add_tokens(&tokens);
+        self.tokens_added(&tokens);
+        added
+    }
+
+    /// Updates the cached information about the vocabulary after `tokens` were added.
+    fn tokens_added(&mut self, tokens: &[tokenizers::AddedToken]) {
+        let longest = tokens.iter().map(|token| token.content.len()).max();
+        self.max_token_length = self.max_token_length.max(longest.unwrap_or_default());
+        self.tokenize_cache.clear();
+        self.literal_tokenizer = LiteralTokenizer::default();
     }
 }
 
 impl HuggingFaceTokenizer {
+    /// Tokenizes and decodes a short text, so that the lazily-initialized state of the
+    /// tokenizer (such as its pre-tokenizer's regular expressions) is built.
+    ///
+    /// This bypasses the tokenize cache, so it does not evict any cached tokenizations.
+    pub(crate) fn prepare(&self) {
+        if let Ok(encoding) = self.encode(&self.tokenizer, "Hello, world!", true) {
+            let _ = self.tokenizer.decode(encoding.get_ids(), true);
+        }
+    }
+
     pub(crate) fn id(&self, token: &[u8]) -> Option<TokenId> {
-        self.tokenizer
-            .token_to_id(std::str::from_utf8(token).unwrap())
+        // The vocabulary is made of strings, so a token that is not valid UTF-8 is not in it.
+        self.tokenizer.token_to_id(std::str::from_utf8(token).ok()?)
+    }
+
+    /// Returns the text that tokenization operates on, after the configured normalizer.
+    ///
+    /// If there is no normalizer, or it fails, the text is returned unchanged.
+    pub(crate) fn normalize(&self, text: &str) -> String {
+        use tokenizers::{NormalizedString, Normalizer};
+
+        let Some(normalizer) = self.tokenizer.get_normalizer() else {
+            return text.to_string();
+        };
+        let mut normalized = NormalizedString::from(text);
+        match normalizer.normalize(&mut normalized) {
+            Ok(()) => normalized.get().to_string(),
+            Err(_) => text.to_string(),
+        }
     }
 
     /// Converts a token index to the token it represents in this tokenizer.
@@ -33,11 +182,31 @@ impl HuggingFaceTokenizer {
         self.tokenizer.get_vocab_size(false)
     }
 
+    /// Returns the number of tokens in the tokenizer, including added tokens.
+    pub(crate) fn vocab_size_with_added(&self) -> usize {
+        self.tokenizer.get_vocab_size(true)
+    }
+
     /// Returns whether the tokenizer is empty.
     pub(crate) fn is_empty(&self) -> bool {
         self.tokenizer.get_vocab_size(false) == 0
     }
 
+    /// Returns the length in bytes of the longest token in the vocabulary.
+    ///
+    /// This is measured on the vocabulary's token strings, which are at least as long
+    /// as the bytes they decode to.
+    pub(crate) fn max_token_byte_length(&self) -> usize {
+        self.max_token_length
+    }
+
+    /// Returns the IDs of the tokens that are not valid UTF-8 by themselves.
+    ///
+    /// Hugging Face vocabularies are stored as strings, so this is always empty.
+    pub(crate) fn non_utf8_token_ids(&self) -> Vec<TokenId> {
+        vec![]
+    }
+
     /// Tokenize a `text` with this tokenizer.
     ///
     /// `bos` controls whether a beginning-of-string token should be inserted.
@@ -46,30 +215,536 @@ impl HuggingFaceTokenizer {
         text: &str,
         bos: bool,
     ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
-        let encoding = self
-            .tokenizer
+        if let Some(tokens) = self.tokenize_cache.get(text, bos) {
+            return Ok(tokens);
+        }
+
+        let tokens = self.tokenize_impl(&self.tokenizer, text, bos)?;
+        self.tokenize_cache.insert(text, bos, &tokens);
+        Ok(tokens)
+    }
+
+    /// Tokenize a `text` with this tokenizer, returning only the token IDs.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted.
+    pub(crate) fn tokenize_ids(
+        &self,
+        text: &str,
+        bos: bool,
+    ) -> Result<Vec<TokenId>, TokenizationError> {
+        if let Some(tokens) = self.tokenize_cache.get(text, bos) {
+            return Ok(tokens.into_iter().map(|(_, id)| id).collect());
+        }
+
+        let encoding = self.encode(&self.tokenizer, text, bos)?;
+        // Only build the token bytes if they are going to be cached.
+        if self.tokenize_cache.capacity > 0 {
+            self.tokenize_cache
+                .insert(text, bos, &self.encoding_tokens(&encoding));
+        }
+        Ok(encoding.get_ids().to_vec())
+    }
+
+    /// Tokenize a `text` with this tokenizer.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted.
+    /// `split_special` controls whether special tokens in the text are tokenized as
+    /// themselves; otherwise, they are tokenized as regular text.
+    pub(crate) fn tokenize_with_special(
+        &self,
+        text: &str,
+        bos: bool,
+        split_special: bool,
+    ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        if split_special {
+            return self.tokenize(text, bos);
+        }
+
+        // The `tokenizers` crate always splits out added tokens, so tokenize with a copy
+        // of the tokenizer that does not have the special ones.
+        let literal = self
+            .literal_tokenizer
+            .get_or_build(|| self.build_literal_tokenizer())
+            .map_err(|e| TokenizationError::TokenizationFailed { error: e })?;
+        self.tokenize_impl(&literal, text, bos)
+    }
+
+    /// Builds a copy of the tokenizer without its special added tokens.
+    fn build_literal_tokenizer(&self) -> Result<tokenizers::Tokenizer, tokenizers::Error> {
+        let mut json: serde_json::Value = serde_json::from_str(&self.tokenizer.to_string(false)?)?;
+        if let Some(added_tokens) = json
+            .get_mut("added_tokens")
+            .and_then(|added_tokens| added_tokens.as_array_mut())
+        {
+            added_tokens
+                .retain(|token| token.get("special") != Some(&serde_json::Value::Bool(true)));
+        }
+
+        json.to_string().parse()
+    }
+
+    fn encode(
+        &self,
+        tokenizer: &tokenizers::Tokenizer,
+        text: &str,
+        bos: bool,
+    ) -> Result<tokenizers::Encoding, TokenizationError> {
+        let encoding = tokenizer
             .encode(text, false)
             .map_err(|e| TokenizationError::TokenizationFailed { error: e })?;
 
-        let encoding = self
-            .tokenizer
+        tokenizer
             .post_process(encoding, None, bos)
-            .map_err(|e| TokenizationError::TokenizationFailed { error: e })?;
+            .map_err(|e| TokenizationError::TokenizationFailed { error: e })
+    }
+
+    fn tokenize_impl(
+        &self,
+        tokenizer: &tokenizers::Tokenizer,
+        text: &str,
+        bos: bool,
+    ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        let encoding = self.encode(tokenizer, text, bos)?;
+        Ok(self.encoding_tokens(&encoding))
+    }
 
-        Ok(encoding
+    fn encoding_tokens(&self, encoding: &tokenizers::Encoding) -> Vec<(Vec<u8>, TokenId)> {
+        encoding
             .get_tokens()
             .iter()
-            .map(|t| t.as_bytes().to_vec())
+            .map(|t| {
+                if self.replace_metaspace_in_tokens {
+                    t.replace('\u{2581}', " ").into_bytes()
+                } else {
+                    t.as_bytes().to_vec()
+                }
+            })
             .zip(encoding.get_ids().iter().copied())
-            .collect())
+            .collect()
+    }
+
+    /// Returns the text of `id` if it is a special token, for showing it while debugging.
+    ///
+    /// A token is special if decoding skips it when skipping special tokens.
+    pub(crate) fn special_token_text(&self, id: TokenId) -> Option<String> {
+        let skipped = self.tokenizer.decode(&[id], true).ok()?.is_empty();
+        skipped.then(|| self.tokenizer.id_to_token(id)).flatten()
     }
 
     /// Decode a list `tokens` with this tokenizer.
+    ///
+    /// The text is produced by the tokenizer's configured decoder (e.g. ByteLevel or
+    /// Metaspace), so that the spacing encoded in the tokens is restored.
     pub(crate) fn decode(&self, tokens: Vec<TokenId>, skip_special_tokens: bool) -> Vec<u8> {
-        self.tokenizer
+        let decoded = self
+            .tokenizer
             .decode(&tokens, skip_special_tokens)
-            .expect("Cannot decode token from tokenizer.")
-            .as_bytes()
-            .to_vec()
+            .expect("Cannot decode token from tokenizer.");
+
+        let decoded = if self.clean_up_tokenization_spaces {
+            tokenizers::decoders::wordpiece::cleanup(&decoded)
+        } else {
+            decoded
+        };
+
+        decoded.into_bytes()
+    }
+
+    /// Decode a list `tokens` with this tokenizer, returning the range of the output
+    /// that each token produced.
+    ///
+    /// The tokenizer's decoder may change the text of a token depending on the tokens
+    /// around it, so each span is found by decoding the tokens up to and including it.
+    pub(crate) fn decode_with_spans(
+        &self,
+        tokens: &[TokenId],
+        skip_special_tokens: bool,
+    ) -> (Vec<u8>, Vec<Range<usize>>) {
+        let decoded = self.decode(tokens.to_vec(), skip_special_tokens);
+
+        let mut spans = Vec::with_capacity(tokens.len());
+        let mut start = 0;
+        for i in 1..=tokens.len() {
+            let end = self
+                .decode(tokens[..i].to_vec(), skip_special_tokens)
+                .len()
+                .clamp(start, decoded.len());
+            spans.push(start..end);
+            start = end;
+        }
+
+        (decoded, spans)
+    }
+}
+
+/// The value of the `TOKENIZERS_PARALLELISM` environment variable that enables or disables
+/// the `tokenizers` library's thread pool.
+fn parallelism_env_value(enabled: bool) -> &'static str {
+    if enabled {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+type TokenizeCacheEntry = ((String, bool), Vec<(Vec<u8>, TokenId)>);
+
+/// A least-recently-used cache of tokenization results.
+#[derive(Debug, Default)]
+struct TokenizeCache {
+    capacity: usize,
+    /// The cached results, most recently used first.
+    entries: Mutex<VecDeque<TokenizeCacheEntry>>,
+}
+impl TokenizeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn get(&self, text: &str, bos: bool) -> Option<Vec<(Vec<u8>, TokenId)>> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries
+            .iter()
+            .position(|((t, b), _)| t == text && *b == bos)?;
+        let entry = entries.remove(index)?;
+        let tokens = entry.1.clone();
+        entries.push_front(entry);
+        Some(tokens)
+    }
+
+    fn insert(&self, text: &str, bos: bool, tokens: &[(Vec<u8>, TokenId)]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.truncate(self.capacity - 1);
+        entries.push_front(((text.to_owned(), bos), tokens.to_vec()));
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+impl Clone for TokenizeCache {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            entries: Mutex::new(self.entries.lock().unwrap().clone()),
+        }
+    }
+}
+
+/// A tokenizer that is built when it is first needed, and then reused.
+#[derive(Debug, Default)]
+struct LiteralTokenizer(Mutex<Option<Arc<tokenizers::Tokenizer>>>);
+impl LiteralTokenizer {
+    fn get_or_build(
+        &self,
+        build: impl FnOnce() -> Result<tokenizers::Tokenizer, tokenizers::Error>,
+    ) -> Result<Arc<tokenizers::Tokenizer>, tokenizers::Error> {
+        let mut tokenizer = self.0.lock().unwrap();
+        if let Some(tokenizer) = &*tokenizer {
+            return Ok(tokenizer.clone());
+        }
+        let built = Arc::new(build()?);
+        *tokenizer = Some(built.clone());
+        Ok(built)
+    }
+}
+impl Clone for LiteralTokenizer {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ByteLevel BPE tokenizer, in the style of GPT-2, whose vocabulary encodes
+    /// leading spaces as `Ġ`.
+    fn byte_level_tokenizer() -> HuggingFaceTokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [
+                {
+                    "id": 11,
+                    "content": "<|user|>",
+                    "single_word": false,
+                    "lstrip": false,
+                    "rstrip": false,
+                    "normalized": false,
+                    "special": true
+                }
+            ],
+            "normalizer": null,
+            "pre_tokenizer": {
+                "type": "ByteLevel",
+                "add_prefix_space": false,
+                "trim_offsets": true,
+                "use_regex": true
+            },
+            "post_processor": null,
+            "decoder": {
+                "type": "ByteLevel",
+                "add_prefix_space": true,
+                "trim_offsets": true,
+                "use_regex": true
+            },
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": null,
+                "continuing_subword_prefix": null,
+                "end_of_word_suffix": null,
+                "fuse_unk": false,
+                "vocab": {
+                    "Hello": 0, "Ġworld": 1, "Ġ,": 2, "Ġ!": 3,
+                    "<": 4, "|": 5, "u": 6, "s": 7, "e": 8, "r": 9, ">": 10
+                },
+                "merges": []
+            }
+        }"#;
+
+        HuggingFaceTokenizer::new(json.parse().unwrap())
+    }
+
+    #[test]
+    fn test_clean_up_tokenization_spaces() {
+        let mut tokenizer = byte_level_tokenizer();
+        assert_eq!(tokenizer.decode(vec![0, 2, 1, 3], true), b"Hello , world !");
+
+        tokenizer.set_clean_up_tokenization_spaces(true);
+        assert_eq!(tokenizer.decode(vec![0, 2, 1, 3], true), b"Hello, world!");
+        assert_eq!(tokenizer.decode(vec![0, 1], true), b"Hello world");
+
+        tokenizer.set_clean_up_tokenization_spaces(false);
+        assert_eq!(tokenizer.decode(vec![0, 2, 1, 3], true), b"Hello , world !");
+    }
+
+    #[test]
+    fn test_tokenize_with_special() {
+        let tokenizer = byte_level_tokenizer();
+        let token_ids = |split_special| -> Vec<TokenId> {
+            tokenizer
+                .tokenize_with_special("<|user|>us", false, split_special)
+                .unwrap()
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect()
+        };
+
+        assert_eq!(token_ids(true), vec![11, 6, 7]);
+        assert_eq!(token_ids(false), vec![4, 5, 6, 7, 8, 9, 5, 10, 6, 7]);
+
+        // The copy without special tokens is built once, and reused.
+        let literal = |tokenizer: &HuggingFaceTokenizer| {
+            tokenizer.literal_tokenizer.0.lock().unwrap().clone()
+        };
+        let built = literal(&tokenizer).unwrap();
+        token_ids(false);
+        assert!(Arc::ptr_eq(&built, &literal(&tokenizer).unwrap()));
+
+        // Adding tokens rebuilds it.
+        let mut tokenizer = tokenizer.clone();
+        tokenizer.add_special_tokens(&["<|bot|>"]);
+        assert!(literal(&tokenizer).is_none());
+    }
+
+    #[test]
+    fn test_set_parallelism() {
+        // The environment variable is process-global, so only check the values that would
+        // be set, rather than racing the other tests by setting it.
+        assert_eq!(parallelism_env_value(true), "true");
+        assert_eq!(parallelism_env_value(false), "false");
+    }
+
+    #[test]
+    fn test_special_token_text() {
+        let tokenizer = byte_level_tokenizer();
+        assert_eq!(
+            tokenizer.special_token_text(11).as_deref(),
+            Some("<|user|>")
+        );
+        assert_eq!(tokenizer.special_token_text(0), None);
+
+        let tokenizer = crate::Tokenizer::from(tokenizer);
+        assert_eq!(
+            tokenizer.decode_debug(&[11, 0, 1, 11]),
+            "<|user|>Hello world<|user|>"
+        );
+    }
+
+    #[test]
+    fn test_decode_with_spans() {
+        let tokenizer = byte_level_tokenizer();
+        let (text, spans) = tokenizer.decode_with_spans(&[0, 2, 1], true);
+        assert_eq!(text, b"Hello , world");
+        assert_eq!(spans, vec![0..5, 5..7, 7..13]);
+    }
+
+    #[test]
+    fn test_tokenize_cache() {
+        let mut tokenizer = byte_level_tokenizer();
+        tokenizer.set_tokenize_cache_capacity(2);
+        let cached_texts = |tokenizer: &HuggingFaceTokenizer| -> Vec<String> {
+            let entries = tokenizer.tokenize_cache.entries.lock().unwrap();
+            entries.iter().map(|((text, _), _)| text.clone()).collect()
+        };
+
+        let tokens = tokenizer.tokenize("Hello world", false).unwrap();
+        assert_eq!(tokenizer.tokenize("Hello world", false).unwrap(), tokens);
+        tokenizer.tokenize("Hello", false).unwrap();
+        assert_eq!(cached_texts(&tokenizer), ["Hello", "Hello world"]);
+
+        // Preparing the tokenizer does not evict anything.
+        tokenizer.prepare();
+        assert_eq!(cached_texts(&tokenizer), ["Hello", "Hello world"]);
+
+        // Using a result makes it the most recent, so the other one is evicted.
+        tokenizer.tokenize("Hello world", false).unwrap();
+        tokenizer.tokenize("us", false).unwrap();
+        assert_eq!(cached_texts(&tokenizer), ["us", "Hello world"]);
+    }
+
+    #[test]
+    fn test_tokenize_ids_cache() {
+        let mut tokenizer = byte_level_tokenizer();
+        tokenizer.set_tokenize_cache_capacity(2);
+
+        let ids = tokenizer.tokenize_ids("Hello world", false).unwrap();
+        let cached = tokenizer
+            .tokenize_cache
+            .get("Hello world", false)
+            .expect("the result should be cached");
+        assert_eq!(cached.iter().map(|(_, id)| *id).collect::<Vec<_>>(), ids);
+
+        // The second call is served from the cache.
+        assert_eq!(tokenizer.tokenize_ids("Hello world", false).unwrap(), ids);
+        assert_eq!(tokenizer.tokenize_cache.entries.lock().unwrap().len(), 1);
+        assert_eq!(tokenizer.tokenize("Hello world", false).unwrap(), cached);
+    }
+
+    #[test]
+    fn test_add_tokens() {
+        let mut tokenizer = byte_level_tokenizer();
+        tokenizer.set_tokenize_cache_capacity(1);
+        let token_ids = |tokenizer: &HuggingFaceTokenizer| -> Vec<TokenId> {
+            tokenizer
+                .tokenize("<|bot|>us", false)
+                .unwrap()
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect()
+        };
+        // `b`, `o` and `t` are not in the vocabulary, so they are dropped.
+        assert_eq!(token_ids(&tokenizer), vec![4, 5, 5, 10, 6, 7]);
+
+        assert_eq!(tokenizer.add_special_tokens(&["<|bot|>", "<|user|>"]), 1);
+        assert_eq!(tokenizer.add_tokens(&["us"]), 1);
+        assert_eq!(tokenizer.vocab_size_with_added(), 14);
+        assert_eq!(token_ids(&tokenizer), vec![12, 13]);
+        assert_eq!(tokenizer.decode(vec![12, 13], true), b"us");
+    }
+
+    #[test]
+    fn test_id() {
+        let tokenizer = byte_level_tokenizer();
+        assert_eq!(tokenizer.id("Ġworld".as_bytes()), Some(1));
+        assert_eq!(tokenizer.id(b"<|user|>"), Some(11));
+        assert_eq!(tokenizer.id(b"world"), None);
+        assert_eq!(tokenizer.id(&[0xE2, 0x82]), None);
+    }
+
+    #[test]
+    fn test_vocab_size_with_added() {
+        let tokenizer = byte_level_tokenizer();
+        assert_eq!(tokenizer.len(), 11);
+        assert_eq!(tokenizer.vocab_size_with_added(), 12);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": {
+                "type": "Sequence",
+                "normalizers": [
+                    { "type": "Lowercase" },
+                    { "type": "Replace", "pattern": { "String": " " }, "content": "▁" }
+                ]
+            },
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "<unk>": 0 },
+                "unk_token": "<unk>"
+            }
+        }"#;
+        let tokenizer = HuggingFaceTokenizer::new(json.parse().unwrap());
+        assert_eq!(tokenizer.normalize("Hello World"), "hello▁world");
+
+        assert_eq!(
+            byte_level_tokenizer().normalize("Hello World"),
+            "Hello World"
+        );
+    }
+
+    #[test]
+    fn test_replace_metaspace_in_tokens() {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": {
+                "type": "Metaspace",
+                "replacement": "▁",
+                "add_prefix_space": true
+            },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "<unk>": 0, "▁hello": 1, "▁world": 2 },
+                "unk_token": "<unk>"
+            }
+        }"#;
+        let mut tokenizer = HuggingFaceTokenizer::new(json.parse().unwrap());
+        let tokens = |tokenizer: &HuggingFaceTokenizer| -> Vec<Vec<u8>> {
+            tokenizer
+                .tokenize("hello world", false)
+                .unwrap()
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect()
+        };
+
+        assert_eq!(
+            tokens(&tokenizer),
+            vec!["▁hello".as_bytes(), "▁world".as_bytes()]
+        );
+
+        tokenizer.set_replace_metaspace_in_tokens(true);
+        assert_eq!(
+            tokens(&tokenizer),
+            vec![b" hello".to_vec(), b" world".to_vec()]
+        );
     }
 }
@@ -1,16 +1,30 @@
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     error::Error,
     fmt::Display,
+    ops::Range,
     path::{Path, PathBuf},
     str::FromStr,
 };
 
 use thiserror::Error;
 
+mod chat;
+pub use chat::*;
+mod compare;
+pub use compare::*;
 mod embedded;
 pub use embedded::*;
+mod handle;
+pub use handle::*;
 mod huggingface;
 pub use huggingface::*;
+mod sentencepiece;
+mod stop;
+pub use stop::*;
+mod stream;
+pub use stream::*;
 
 /// The identifier of a token in a tokenizer.
 pub type TokenId = u32;
@@ -37,20 +51,137 @@ pub enum TokenizationError {
 #[error("error loading tokenizer from {path}: {error}")]
 pub struct TokenizerLoadError {
     /// The path to the tokenizer.
+    ///
+    /// For sources that are not files, such as a remote repository, this is the path
+    /// to the model instead; the error describes the source.
     pub path: PathBuf,
     /// The error that occurred during loading.
+    ///
+    /// This describes the source that failed, and its [source](Error::source) is the
+    /// underlying cause.
+    #[source]
     pub error: Box<dyn Error + Send + Sync>,
 }
 
 impl TokenizerLoadError {
-    fn new(path: impl Into<PathBuf>, error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+    fn new(
+        path: impl Into<PathBuf>,
+        kind: TokenizerLoadErrorKind,
+        context: impl Into<String>,
+        error: impl Into<Box<dyn Error + Send + Sync>>,
+    ) -> Self {
         Self {
             path: path.into(),
-            error: error.into(),
+            error: Box::new(SourceError {
+                context: context.into(),
+                kind,
+                error: error.into(),
+            }),
+        }
+    }
+
+    /// Returns the kind of the error, so that callers can decide whether to try another source.
+    ///
+    /// The kind is worked out from [Self::error]; errors that were not returned by
+    /// [TokenizerSource::retrieve] are classified by their cause.
+    pub fn kind(&self) -> TokenizerLoadErrorKind {
+        if let Some(error) = self.error.downcast_ref::<SourceError>() {
+            error.kind
+        } else if let Some(error) = self.error.downcast_ref::<TokenizerFallbackError>() {
+            error.kind()
+        } else {
+            TokenizerLoadErrorKind::classify(&*self.error, TokenizerLoadErrorKind::Other)
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The kind of a [TokenizerLoadError].
+pub enum TokenizerLoadErrorKind {
+    /// The tokenizer file does not exist.
+    NotFound,
+    /// The tokenizer was read, but is not a valid tokenizer.
+    Parse,
+    /// The tokenizer could not be fetched from a remote repository.
+    Network,
+    /// Any other error, such as a file that could not be read, or several sources that
+    /// failed in different ways.
+    Other,
+}
+impl TokenizerLoadErrorKind {
+    /// Classifies `error` by the first error in its chain that is recognized, returning
+    /// `default` if there is none.
+    fn classify(error: &(dyn Error + 'static), default: Self) -> Self {
+        let mut next = Some(error);
+        while let Some(error) = next {
+            if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                return match error.kind() {
+                    std::io::ErrorKind::NotFound => Self::NotFound,
+                    std::io::ErrorKind::InvalidData => Self::Parse,
+                    _ => default,
+                };
+            }
+            if error.is::<serde_json::Error>() {
+                return Self::Parse;
+            }
+            next = error.source();
+        }
+        default
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("{context}: {error}")]
+/// An error that occurred while loading from a tokenizer source, with a description of it.
+struct SourceError {
+    context: String,
+    kind: TokenizerLoadErrorKind,
+    #[source]
+    error: Box<dyn Error + Send + Sync>,
+}
+
+#[derive(Debug)]
+/// The error of a [TokenizerSource::Fallback] whose sources all failed.
+///
+/// Its [source](Error::source) is the error of the first source; the errors of all of the
+/// sources are in [Self::errors].
+pub struct TokenizerFallbackError {
+    /// The error of each source, in the order they were tried.
+    pub errors: Vec<TokenizerLoadError>,
+}
+impl TokenizerFallbackError {
+    /// Returns the kind of error shared by all of the sources, or
+    /// [TokenizerLoadErrorKind::Other] if they failed in different ways.
+    pub fn kind(&self) -> TokenizerLoadErrorKind {
+        match self.errors.split_first() {
+            Some((first, rest)) if rest.iter().all(|e| e.kind() == first.kind()) => first.kind(),
+            _ => TokenizerLoadErrorKind::Other,
+        }
+    }
+}
+impl Display for TokenizerFallbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.errors.is_empty() {
+            return write!(f, "no tokenizer sources to fall back on");
+        }
+        write!(f, "all tokenizer sources failed: ")?;
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+impl Error for TokenizerFallbackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.errors
+            .first()
+            .map(|error| error as &(dyn Error + 'static))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 /// The source of a tokenizer.
 pub enum TokenizerSource {
@@ -60,6 +191,12 @@ pub enum TokenizerSource {
     /// guaranteed to be available for all models.
     Embedded,
 
+    /// Read the vocabulary from the model like [Self::Embedded], but only for decoding.
+    ///
+    /// This skips building the map from tokens to IDs, saving memory when the model is only
+    /// used to convert token IDs to text; see [EmbeddedTokenizer::new_decode_only].
+    EmbeddedDecodeOnly,
+
     /// Read a Hugging Face tokenizer from a local Hugging Face tokenizer file.
     HuggingFaceTokenizerFile(PathBuf),
 
@@ -71,6 +208,17 @@ pub enum TokenizerSource {
     /// and may store files locally, so it is not recommended for production use.
     #[cfg(feature = "tokenizers-remote")]
     HuggingFaceRemote(String),
+
+    /// Read the vocabulary from a SentencePiece model file (usually `tokenizer.model`), and
+    /// use the same tokenizer as [Self::Embedded].
+    ///
+    /// See [EmbeddedTokenizer::from_sentencepiece].
+    SentencePieceFile(PathBuf),
+
+    /// Try each of the sources in order, using the first one that succeeds.
+    ///
+    /// If all of them fail, the error lists the errors of every source.
+    Fallback(Vec<TokenizerSource>),
 }
 impl TokenizerSource {
     /// Retrieve the tokenizer from the source.
@@ -80,27 +228,63 @@ impl TokenizerSource {
     pub fn retrieve(self, model_path: &Path) -> Result<Tokenizer, TokenizerLoadError> {
         let _ = model_path;
 
+        use TokenizerLoadErrorKind as Kind;
+
         Ok(match self {
             #[cfg(feature = "tokenizers-remote")]
             Self::HuggingFaceRemote(identifier) => HuggingFaceTokenizer::new(
-                tokenizers::Tokenizer::from_pretrained(&identifier, None)
-                    .map_err(|error| TokenizerLoadError::new(model_path, error))?,
+                tokenizers::Tokenizer::from_pretrained(&identifier, None).map_err(|error| {
+                    let kind = Kind::classify(&*error, Kind::Network);
+                    let context = format!("could not fetch `{identifier}` from Hugging Face");
+                    TokenizerLoadError::new(model_path, kind, context, error)
+                })?,
             )
             .into(),
 
             Self::HuggingFaceTokenizerFile(path) => HuggingFaceTokenizer::new(
-                tokenizers::Tokenizer::from_file(&path)
-                    .map_err(|error| TokenizerLoadError::new(path, error))?,
+                tokenizers::Tokenizer::from_file(&path).map_err(|error| {
+                    let kind = Kind::classify(&*error, Kind::Other);
+                    let context = "could not load the Hugging Face tokenizer file";
+                    TokenizerLoadError::new(&path, kind, context, error)
+                })?,
             )
             .into(),
 
-            Self::HuggingFaceTokenizerString(s) => HuggingFaceTokenizer::new(
-                tokenizers::Tokenizer::from_str(&s)
-                    .map_err(|error| TokenizerLoadError::new(model_path, error))?,
-            )
-            .into(),
+            Self::HuggingFaceTokenizerString(s) => {
+                HuggingFaceTokenizer::new(tokenizers::Tokenizer::from_str(&s).map_err(|error| {
+                    let context = "could not parse the Hugging Face tokenizer string";
+                    TokenizerLoadError::new(model_path, Kind::Parse, context, error)
+                })?)
+                .into()
+            }
 
             Self::Embedded => EmbeddedTokenizer::default().into(),
+            Self::EmbeddedDecodeOnly => EmbeddedTokenizer::new_decode_only().into(),
+
+            Self::SentencePieceFile(path) => {
+                let context = "could not load the SentencePiece model";
+                let model = std::fs::read(&path).map_err(|error| {
+                    let kind = Kind::classify(&error, Kind::Other);
+                    TokenizerLoadError::new(&path, kind, context, error)
+                })?;
+                EmbeddedTokenizer::from_sentencepiece(&model)
+                    .map_err(|error| TokenizerLoadError::new(&path, Kind::Parse, context, error))?
+                    .into()
+            }
+
+            Self::Fallback(sources) => {
+                let mut errors = vec![];
+                for source in sources {
+                    match source.retrieve(model_path) {
+                        Ok(tokenizer) => return Ok(tokenizer),
+                        Err(error) => errors.push(error),
+                    }
+                }
+                return Err(TokenizerLoadError {
+                    path: model_path.to_owned(),
+                    error: Box::new(TokenizerFallbackError { errors }),
+                });
+            }
         })
     }
 }
@@ -139,6 +323,33 @@ impl Tokenizer {
         }
     }
 
+    /// Converts a word to the token ID it represents in this tokenizer, applying the same
+    /// normalization as tokenization.
+    ///
+    /// Unlike [Self::id], this finds `"hello"` in a SentencePiece vocabulary that stores it
+    /// as `"▁hello"`. Returns `None` if the word is not a single token.
+    pub fn id_normalized(&self, text: &str) -> Option<TokenId> {
+        match self {
+            Tokenizer::Embedded(v) => v.id_normalized(text),
+            Tokenizer::HuggingFace(v) => match v.tokenize_ids(text, false).ok()?.as_slice() {
+                [id] => Some(*id),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the text that tokenization operates on, after normalization.
+    ///
+    /// For embedded tokenizers, this adds the dummy prefix if it is enabled. For Hugging Face
+    /// tokenizers, this applies the configured normalizer (such as NFC or lowercasing), but
+    /// not the pre-tokenizer. This helps explain why two tokenizers split a text differently.
+    pub fn normalize(&self, text: &str) -> String {
+        match self {
+            Tokenizer::Embedded(v) => v.normalize(text).into_owned(),
+            Tokenizer::HuggingFace(v) => v.normalize(text),
+        }
+    }
+
     /// Converts a token index to the token it represents in this tokenizer.
     pub fn token(&self, idx: usize) -> Vec<u8> {
         match self {
@@ -147,7 +358,24 @@ impl Tokenizer {
         }
     }
 
+    /// Converts a token index to the token it represents in this tokenizer, or `None` if
+    /// there is no such token.
+    ///
+    /// Unlike [Self::token], this borrows the bytes of embedded tokens rather than copying
+    /// them. Hugging Face tokens are decoded, so they are always owned.
+    pub fn token_ref(&self, idx: usize) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Tokenizer::Embedded(v) => v.token_bytes(idx).map(Cow::Borrowed),
+            Tokenizer::HuggingFace(v) => {
+                (idx < v.vocab_size_with_added()).then(|| Cow::Owned(v.token(idx)))
+            }
+        }
+    }
+
     /// Returns the number of tokens in the tokenizer.
+    ///
+    /// For Hugging Face tokenizers, this excludes added tokens;
+    /// see [Self::vocab_size_with_added].
     pub fn len(&self) -> usize {
         match self {
             Tokenizer::Embedded(v) => v.len(),
@@ -155,6 +383,47 @@ impl Tokenizer {
         }
     }
 
+    /// Returns the number of tokens in the tokenizer, including tokens added on top of
+    /// the base vocabulary.
+    ///
+    /// Hugging Face tokenizers can have added tokens (such as special tokens) that are not
+    /// counted by [Self::len], but that models still produce logits for. Use this to size
+    /// buffers indexed by token ID. For embedded tokenizers, this is the same as [Self::len].
+    pub fn vocab_size_with_added(&self) -> usize {
+        match self {
+            Tokenizer::Embedded(v) => v.len(),
+            Tokenizer::HuggingFace(v) => v.vocab_size_with_added(),
+        }
+    }
+
+    /// Guesses the chat format that the model expects from the special tokens in this tokenizer.
+    ///
+    /// Returns `None` if the format cannot be determined confidently; see [PromptFormat].
+    pub fn detect_prompt_format(&self) -> Option<PromptFormat> {
+        PromptFormat::detect(self)
+    }
+
+    /// Builds any state of the tokenizer that is otherwise built on first use.
+    ///
+    /// Call this before serving requests, so that the first one does not pay for it.
+    /// Embedded tokenizers are built as their vocabulary is loaded, so this does nothing
+    /// for them. Hugging Face tokenizers are primed by tokenizing and decoding a short text.
+    pub fn prepare(&self) {
+        match self {
+            Tokenizer::Embedded(_) => {}
+            Tokenizer::HuggingFace(v) => v.prepare(),
+        }
+    }
+
+    /// Sets whether Hugging Face tokenizers may use their internal thread pool.
+    ///
+    /// This is process-global, and applies to every Hugging Face tokenizer; see
+    /// [HuggingFaceTokenizer::set_parallelism]. Embedded tokenizers are always
+    /// single-threaded, so they are not affected.
+    pub fn set_parallelism(enabled: bool) {
+        HuggingFaceTokenizer::set_parallelism(enabled);
+    }
+
     /// Returns whether the tokenizer is empty.
     pub fn is_empty(&self) -> bool {
         match self {
@@ -163,9 +432,51 @@ impl Tokenizer {
         }
     }
 
+    /// Returns the length in bytes of the longest token in the tokenizer.
+    ///
+    /// This is useful for sizing buffers that need to hold any single token.
+    pub fn max_token_byte_length(&self) -> usize {
+        match self {
+            Tokenizer::Embedded(v) => v.max_token_byte_length(),
+            Tokenizer::HuggingFace(v) => v.max_token_byte_length(),
+        }
+    }
+
+    /// Returns the longest token in the vocabulary that `text` starts with, and its length
+    /// in bytes.
+    ///
+    /// This is the greedy first step of tokenizing `text`; it may differ from the first
+    /// token produced by [Self::tokenize]. Only embedded tokenizers support this, so it
+    /// returns [None] for Hugging Face tokenizers.
+    pub fn longest_prefix_token(&self, text: &[u8]) -> Option<(TokenId, usize)> {
+        match self {
+            Tokenizer::Embedded(v) => v.longest_prefix_token(text),
+            Tokenizer::HuggingFace(_) => None,
+        }
+    }
+
+    /// Returns the IDs of the tokens whose bytes are not valid UTF-8 by themselves.
+    ///
+    /// These tokens (for example, byte-level tokens) must be handled as bytes, and are only
+    /// valid UTF-8 when combined with other tokens. See [TokenUtf8Buffer](crate::TokenUtf8Buffer).
+    pub fn non_utf8_token_ids(&self) -> Vec<TokenId> {
+        match self {
+            Tokenizer::Embedded(v) => v.non_utf8_token_ids(),
+            Tokenizer::HuggingFace(v) => v.non_utf8_token_ids(),
+        }
+    }
+
     /// Tokenize a `text` with this tokenizer.
     ///
     /// `bos` controls whether a beginning-of-string token should be inserted.
+    ///
+    /// Each token is returned with its bytes as stored in the vocabulary. For Hugging Face
+    /// tokenizers, these may encode spaces differently from the text (e.g. `"▁hello"` or
+    /// `"Ġhello"` for `" hello"`); see [HuggingFaceTokenizer::set_replace_metaspace_in_tokens].
+    /// Use [Self::decode] to convert tokens back to text.
+    ///
+    /// Use [Self::tokenize_ids] to get only the token IDs, and [Self::ids_to_pairs] to get
+    /// the tokens back from the IDs.
     pub fn tokenize(
         &self,
         text: &str,
@@ -177,6 +488,51 @@ impl Tokenizer {
         }
     }
 
+    /// Tokenize a `text` with this tokenizer, returning only the token IDs.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted.
+    ///
+    /// This is the same as [Self::tokenize], but does not allocate the bytes of each token.
+    pub fn tokenize_ids(&self, text: &str, bos: bool) -> Result<Vec<TokenId>, TokenizationError> {
+        match self {
+            Tokenizer::Embedded(v) => v.tokenize_ids(text, bos),
+            Tokenizer::HuggingFace(v) => v.tokenize_ids(text, bos),
+        }
+    }
+
+    /// Pairs each of `ids` with the bytes of the token it represents, as returned by
+    /// [Self::tokenize].
+    ///
+    /// Unlike [Self::tokenize], the beginning-of-string token is paired with its bytes in
+    /// the vocabulary rather than with no bytes.
+    pub fn ids_to_pairs(&self, ids: &[TokenId]) -> Vec<(Vec<u8>, TokenId)> {
+        ids.iter()
+            .map(|&id| (self.token(id as usize), id))
+            .collect()
+    }
+
+    /// Tokenize a `text` with this tokenizer, controlling whether special tokens are recognized.
+    ///
+    /// `bos` controls whether a beginning-of-string token should be inserted.
+    /// If `split_special` is true, special tokens that appear in the text (e.g. `<|user|>`)
+    /// are tokenized as those tokens; otherwise, they are tokenized as regular text.
+    ///
+    /// Embedded tokenizers do not record which of their tokens are special, so only the tokens
+    /// set with [EmbeddedTokenizer::set_special_token_ids] are split. For Hugging Face
+    /// tokenizers, not splitting special tokens requires a copy of the tokenizer, which makes
+    /// it slower than [Self::tokenize].
+    pub fn tokenize_with_special(
+        &self,
+        text: &str,
+        bos: bool,
+        split_special: bool,
+    ) -> Result<Vec<(Vec<u8>, TokenId)>, TokenizationError> {
+        match self {
+            Tokenizer::Embedded(v) => v.tokenize_with_special(text, bos, split_special),
+            Tokenizer::HuggingFace(v) => v.tokenize_with_special(text, bos, split_special),
+        }
+    }
+
     /// Decode a list `tokens` with this tokenizer.
     pub fn decode(&self, tokens: Vec<TokenId>, bos: bool) -> Vec<u8> {
         match self {
@@ -184,6 +540,93 @@ impl Tokenizer {
             Tokenizer::HuggingFace(v) => v.decode(tokens, bos),
         }
     }
+
+    /// Decode a list `tokens` into text for debugging, showing where the special tokens are.
+    ///
+    /// Special tokens are shown as their text (e.g. `<s>` or `<|im_start|>`), even if an
+    /// embedded tokenizer stores them surrounded by whitespace or as nothing at all, in
+    /// which case `<token:ID>` is shown. The other tokens are decoded as with [Self::decode],
+    /// and invalid token IDs are shown as `<invalid:ID>`.
+    pub fn decode_debug(&self, tokens: &[TokenId]) -> String {
+        let mut text = String::new();
+        let mut run = vec![];
+        let flush = |run: &mut Vec<TokenId>, text: &mut String| {
+            if !run.is_empty() {
+                *text += &String::from_utf8_lossy(&self.decode(std::mem::take(run), false));
+            }
+        };
+
+        for &id in tokens {
+            let special = if id as usize >= self.vocab_size_with_added() {
+                Some(format!("<invalid:{id}>"))
+            } else {
+                match self {
+                    Tokenizer::Embedded(v) => v.special_token_text(id),
+                    Tokenizer::HuggingFace(v) => v.special_token_text(id),
+                }
+            };
+            match special {
+                Some(special) => {
+                    flush(&mut run, &mut text);
+                    text += &special;
+                }
+                None => run.push(id),
+            }
+        }
+        flush(&mut run, &mut text);
+        text
+    }
+
+    /// Decode a list `tokens` with this tokenizer into text with the spaces between words
+    /// restored.
+    ///
+    /// [Self::decode] returns the bytes of embedded tokens as they are stored, which may
+    /// include the SentencePiece metasymbol (`▁`) in place of spaces. This converts it to
+    /// spaces and drops the space at the start of the text, matching `llama.cpp`. Hugging
+    /// Face tokenizers already restore spaces in [Self::decode], so this is the same for them.
+    pub fn decode_normalized(&self, tokens: Vec<TokenId>, bos: bool) -> Vec<u8> {
+        match self {
+            Tokenizer::Embedded(v) => v.decode_normalized(tokens, bos),
+            Tokenizer::HuggingFace(v) => v.decode(tokens, bos),
+        }
+    }
+
+    /// Decode a list `tokens` with this tokenizer, also returning the range of bytes in the
+    /// output that each token produced.
+    ///
+    /// Tokens that produce no output (such as skipped special tokens) have an empty range.
+    pub fn decode_with_spans(
+        &self,
+        tokens: &[TokenId],
+        skip_special_tokens: bool,
+    ) -> (Vec<u8>, Vec<Range<usize>>) {
+        match self {
+            Tokenizer::Embedded(v) => v.decode_with_spans(tokens, skip_special_tokens),
+            Tokenizer::HuggingFace(v) => v.decode_with_spans(tokens, skip_special_tokens),
+        }
+    }
+
+    /// Decode `ids` as they are produced, yielding the text of each token once it is complete.
+    ///
+    /// This uses a [StreamDecoder], so characters split across tokens are buffered until they
+    /// are complete. Together, the items are the same text as [Self::decode_normalized]
+    /// with special tokens skipped. Tokens that do not complete any text yet do not yield
+    /// an item; once `ids` runs out, whatever is still buffered is yielded as a last item
+    /// (see [StreamDecoder::finish]).
+    pub fn decode_iter<'a>(
+        &'a self,
+        mut ids: impl Iterator<Item = TokenId> + 'a,
+    ) -> impl Iterator<Item = String> + 'a {
+        let mut decoder = Some(StreamDecoder::new(self));
+        std::iter::from_fn(move || {
+            let text = match ids.next() {
+                Some(id) => decoder.as_mut()?.feed(&[id]),
+                None => decoder.take()?.finish(),
+            };
+            Some(text)
+        })
+        .filter(|text| !text.is_empty())
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -213,11 +656,7 @@ impl Prompt<'_> {
         beginning_of_sentence: bool,
     ) -> Result<Vec<TokenId>, TokenizationError> {
         Ok(match self {
-            Self::Text(text) => vocab
-                .tokenize(text, beginning_of_sentence)?
-                .iter()
-                .map(|(_, tok)| *tok)
-                .collect(),
+            Self::Text(text) => vocab.tokenize_ids(text, beginning_of_sentence)?,
             Self::Tokens(tokens) => {
                 if let Some(t) = tokens
                     .iter()
@@ -231,6 +670,21 @@ impl Prompt<'_> {
         })
     }
 
+    /// Returns the number of tokens that [Self::to_tokens] would produce for this prompt.
+    ///
+    /// As with [Self::to_tokens], `beginning_of_sentence` only applies to [Self::Text];
+    /// prompts specified as tokens are used as-is, and their token IDs are not validated.
+    pub fn len_tokens(
+        &self,
+        vocab: &Tokenizer,
+        beginning_of_sentence: bool,
+    ) -> Result<usize, TokenizationError> {
+        Ok(match self {
+            Self::Text(text) => vocab.tokenize_ids(text, beginning_of_sentence)?.len(),
+            Self::Tokens(tokens) => tokens.len(),
+        })
+    }
+
     /// Returns whether this prompt is empty.
     pub fn is_empty(&self) -> bool {
         match self {
@@ -304,6 +758,24 @@ impl From<TokenBias> for Vec<(TokenId, f32)> {
     }
 }
 
+impl From<HashMap<TokenId, f32>> for TokenBias {
+    fn from(map: HashMap<TokenId, f32>) -> Self {
+        Self::new(map.into_iter().collect())
+    }
+}
+
+impl FromIterator<(TokenId, f32)> for TokenBias {
+    fn from_iter<I: IntoIterator<Item = (TokenId, f32)>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl From<&TokenBias> for HashMap<TokenId, f32> {
+    fn from(val: &TokenBias) -> Self {
+        val.0.iter().copied().collect()
+    }
+}
+
 impl FromStr for TokenBias {
     type Err = InvalidTokenBias;
 
@@ -360,3 +832,70 @@ impl std::fmt::Display for TokenBias {
         write!(f, "{:?}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_error_kind() {
+        let model_path = Path::new("model.bin");
+        let retrieve = |source: TokenizerSource| match source.retrieve(model_path) {
+            Ok(_) => panic!("expected the source to fail"),
+            Err(error) => error,
+        };
+
+        let error = retrieve(TokenizerSource::HuggingFaceTokenizerFile(
+            "missing.json".into(),
+        ));
+        assert_eq!(error.kind(), TokenizerLoadErrorKind::NotFound);
+        assert!(error.to_string().contains("missing.json"));
+        assert!(error.error.source().is_some());
+
+        let error = retrieve(TokenizerSource::HuggingFaceTokenizerString(
+            "{}".to_string(),
+        ));
+        assert_eq!(error.kind(), TokenizerLoadErrorKind::Parse);
+
+        let error = retrieve(TokenizerSource::Fallback(vec![]));
+        assert_eq!(error.kind(), TokenizerLoadErrorKind::Other);
+
+        // Errors built elsewhere are classified by their cause.
+        let error = TokenizerLoadError {
+            path: model_path.to_owned(),
+            error: Box::new(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+        assert_eq!(error.kind(), TokenizerLoadErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_fallback_source() {
+        let model_path = Path::new("model.bin");
+        let missing = TokenizerSource::HuggingFaceTokenizerFile("missing.json".into());
+
+        let tokenizer = TokenizerSource::Fallback(vec![missing.clone(), TokenizerSource::Embedded])
+            .retrieve(model_path)
+            .unwrap();
+        assert!(matches!(tokenizer, Tokenizer::Embedded(_)));
+
+        let Err(error) =
+            TokenizerSource::Fallback(vec![missing.clone(), missing]).retrieve(model_path)
+        else {
+            panic!("expected all of the sources to fail");
+        };
+        assert_eq!(error.path, model_path);
+        assert_eq!(error.kind(), TokenizerLoadErrorKind::NotFound);
+        assert_eq!(error.error.to_string().matches("missing.json").count(), 2);
+
+        // The error of each source is kept, with its cause.
+        let fallback = error
+            .error
+            .downcast_ref::<TokenizerFallbackError>()
+            .unwrap();
+        assert_eq!(fallback.errors.len(), 2);
+        assert_eq!(fallback.errors[0].path, Path::new("missing.json"));
+        assert!(fallback.errors[0].error.source().is_some());
+        let source = error.error.source().unwrap();
+        assert!(source.is::<TokenizerLoadError>());
+    }
+}
@@ -0,0 +1,238 @@
+use super::{embedded::SENTENCEPIECE_SPACE, EmbeddedTokenizer, EmbeddedTokenizerError, TokenId};
+
+/// The types of pieces in `sentencepiece_model.proto` that need special handling.
+const PIECE_TYPE_UNKNOWN: u64 = 2;
+const PIECE_TYPE_CONTROL: u64 = 3;
+const PIECE_TYPE_USER_DEFINED: u64 = 4;
+const PIECE_TYPE_BYTE: u64 = 6;
+
+/// A field of a protobuf message.
+enum Value<'a> {
+    Varint(u64),
+    Fixed32([u8; 4]),
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+/// Reads the fields of a protobuf message, without knowledge of its schema.
+struct MessageReader<'a> {
+    data: &'a [u8],
+}
+impl<'a> MessageReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], EmbeddedTokenizerError> {
+        if len > self.data.len() {
+            return Err(invalid_model("unexpected end of message"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> Result<u64, EmbeddedTokenizerError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_model("varint is too long"))
+    }
+
+    /// Reads the next field number and value, or returns `None` at the end of the message.
+    fn field(&mut self) -> Result<Option<(u64, Value<'a>)>, EmbeddedTokenizerError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let len = usize::try_from(self.varint()?)
+                    .map_err(|_| invalid_model("length does not fit in memory"))?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => Value::Fixed32(self.take(4)?.try_into().unwrap()),
+            _ => return Err(invalid_model("unsupported wire type")),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+fn invalid_model(reason: &str) -> EmbeddedTokenizerError {
+    EmbeddedTokenizerError::InvalidSentencePieceModel(reason.to_string())
+}
+
+impl EmbeddedTokenizer {
+    /// Create a tokenizer from the bytes of a SentencePiece model (a `.model` file).
+    ///
+    /// The pieces are converted the same way as those of GGML vocabularies: the
+    /// SentencePiece metasymbol (`▁`) is stored as a space, and byte pieces (e.g. `<0x0A>`)
+    /// are stored as the byte they represent. The unknown piece is used as the unknown token,
+    /// and control and user-defined pieces are recognized as special tokens; see
+    /// [Self::set_special_token_ids]. The model's `add_dummy_prefix` setting is kept.
+    pub fn from_sentencepiece(model: &[u8]) -> Result<Self, EmbeddedTokenizerError> {
+        let mut tokenizer = Self::default();
+        let mut special_token_ids = vec![];
+        let mut add_dummy_prefix = true;
+
+        let mut reader = MessageReader::new(model);
+        while let Some((field, value)) = reader.field()? {
+            match (field, value) {
+                // ModelProto.pieces
+                (1, Value::Bytes(piece)) => {
+                    let id = TokenId::try_from(tokenizer.len())
+                        .map_err(|_| invalid_model("too many pieces"))?;
+                    let (token, score, piece_type) = read_piece(piece)?;
+                    match piece_type {
+                        PIECE_TYPE_UNKNOWN => tokenizer.set_unk_token_id(Some(id)),
+                        PIECE_TYPE_CONTROL | PIECE_TYPE_USER_DEFINED => special_token_ids.push(id),
+                        _ => {}
+                    }
+                    let token = match (piece_type, byte_piece(&token)) {
+                        (PIECE_TYPE_BYTE, Some(byte)) => vec![byte],
+                        _ => token
+                            .replace(std::str::from_utf8(SENTENCEPIECE_SPACE).unwrap(), " ")
+                            .into_bytes(),
+                    };
+                    tokenizer.push_token(id, token, score);
+                }
+                // ModelProto.normalizer_spec
+                (3, Value::Bytes(normalizer_spec)) => {
+                    let mut reader = MessageReader::new(normalizer_spec);
+                    while let Some((field, value)) = reader.field()? {
+                        // NormalizerSpec.add_dummy_prefix
+                        if let (3, Value::Varint(value)) = (field, value) {
+                            add_dummy_prefix = value != 0;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if tokenizer.is_empty() {
+            return Err(invalid_model("the model has no pieces"));
+        }
+        tokenizer.set_add_dummy_prefix(add_dummy_prefix);
+        tokenizer.set_special_token_ids(special_token_ids);
+        Ok(tokenizer)
+    }
+}
+
+/// Reads the text, score and type of a `ModelProto.SentencePiece` message.
+fn read_piece(piece: &[u8]) -> Result<(String, f32, u64), EmbeddedTokenizerError> {
+    let mut text = None;
+    let mut score = 0.0;
+    // Pieces are normal unless stated otherwise.
+    let mut piece_type = 1;
+
+    let mut reader = MessageReader::new(piece);
+    while let Some((field, value)) = reader.field()? {
+        match (field, value) {
+            (1, Value::Bytes(bytes)) => {
+                text = Some(
+                    String::from_utf8(bytes.to_vec())
+                        .map_err(|_| invalid_model("piece is not valid UTF-8"))?,
+                );
+            }
+            (2, Value::Fixed32(bytes)) => score = f32::from_le_bytes(bytes),
+            (3, Value::Varint(value)) => piece_type = value,
+            _ => {}
+        }
+    }
+
+    let text = text.ok_or_else(|| invalid_model("piece has no text"))?;
+    Ok((text, score, piece_type))
+}
+
+/// Returns the byte represented by a byte piece, such as `<0x0A>`.
+fn byte_piece(piece: &str) -> Option<u8> {
+    let hex = piece.strip_prefix("<0x")?.strip_suffix('>')?;
+    (hex.len() == 2).then(|| u8::from_str_radix(hex, 16).ok())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tokenizer;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(field << 3 | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn piece(text: &str, score: f32, piece_type: u64) -> Vec<u8> {
+        let mut out = vec![];
+        bytes_field(1, text.as_bytes(), &mut out);
+        varint(2 << 3 | 5, &mut out);
+        out.extend_from_slice(&score.to_le_bytes());
+        varint(3 << 3, &mut out);
+        varint(piece_type, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_from_sentencepiece() {
+        let mut model = vec![];
+        for (text, piece_type) in [
+            ("<unk>", PIECE_TYPE_UNKNOWN),
+            ("<s>", PIECE_TYPE_CONTROL),
+            ("</s>", PIECE_TYPE_CONTROL),
+            ("<0x0A>", PIECE_TYPE_BYTE),
+            ("▁Hello", 1),
+            ("▁world", 1),
+        ] {
+            bytes_field(1, &piece(text, -1.0, piece_type), &mut model);
+        }
+        // A normalizer spec with add_dummy_prefix set.
+        bytes_field(3, &[3 << 3, 1], &mut model);
+
+        let tokenizer = EmbeddedTokenizer::from_sentencepiece(&model).unwrap();
+        assert_eq!(tokenizer.token_bytes(3), Some(&b"\n"[..]));
+        assert_eq!(tokenizer.token_bytes(4), Some(&b" Hello"[..]));
+
+        let tokenizer = Tokenizer::from(tokenizer);
+        let ids = |text| -> Vec<TokenId> {
+            tokenizer
+                .tokenize_with_special(text, true, true)
+                .unwrap()
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect()
+        };
+        assert_eq!(ids("Hello world\n</s>"), vec![1, 4, 5, 3, 2]);
+        assert_eq!(ids("Hello€"), vec![1, 4, 0]);
+    }
+
+    #[test]
+    fn test_invalid_sentencepiece() {
+        assert!(matches!(
+            EmbeddedTokenizer::from_sentencepiece(&[]),
+            Err(EmbeddedTokenizerError::InvalidSentencePieceModel(_))
+        ));
+        assert!(matches!(
+            EmbeddedTokenizer::from_sentencepiece(&[1 << 3 | 2, 10, 0]),
+            Err(EmbeddedTokenizerError::InvalidSentencePieceModel(_))
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+use super::{StreamDecoder, TokenId, Tokenizer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The outcome of pushing a token to a [StopSequenceMatcher].
+pub enum StopDecision {
+    /// No stop sequence has been completed; generation can continue.
+    Continue,
+    /// A stop sequence has been completed; generation should stop.
+    Stop {
+        /// The index of the stop sequence that was completed.
+        index: usize,
+        /// The number of bytes at the end of the decoded text that should be trimmed.
+        ///
+        /// This covers the stop sequence itself, as well as any text that the last
+        /// token produced after it.
+        trim: usize,
+    },
+}
+
+/// Detects when generated tokens complete one of a set of stop sequences.
+///
+/// The tokens are decoded incrementally with a [StreamDecoder], so stop sequences are
+/// matched against the decoded text, even when they span several tokens or end partway
+/// through a token. The trimmed byte counts refer to the text produced by decoding all of
+/// the pushed tokens.
+pub struct StopSequenceMatcher<'a> {
+    decoder: StreamDecoder<'a>,
+    stop_sequences: Vec<String>,
+    /// The longest stop sequence, in bytes.
+    max_len: usize,
+    /// The end of the decoded text, which is long enough to hold all but the last byte
+    /// of any stop sequence.
+    tail: String,
+}
+impl<'a> StopSequenceMatcher<'a> {
+    /// Create a new matcher for the `stop_sequences`, decoding tokens from `tokenizer`.
+    ///
+    /// Empty stop sequences are never matched.
+    pub fn new(tokenizer: &'a Tokenizer, stop_sequences: &[String]) -> Self {
+        let stop_sequences = stop_sequences.to_vec();
+        let max_len = stop_sequences.iter().map(String::len).max().unwrap_or(0);
+        Self {
+            decoder: StreamDecoder::new(tokenizer),
+            stop_sequences,
+            max_len,
+            tail: String::new(),
+        }
+    }
+
+    /// Push the next generated token, and return whether it completed a stop sequence.
+    ///
+    /// If several stop sequences are completed by the same token, the one that starts
+    /// earliest in the text is reported.
+    pub fn push(&mut self, id: TokenId) -> StopDecision {
+        let text = self.decoder.feed(&[id]);
+        if text.is_empty() {
+            return StopDecision::Continue;
+        }
+
+        let new_start = self.tail.len();
+        self.tail.push_str(&text);
+
+        // Only consider matches that end in the new text; earlier matches have already
+        // been reported.
+        let decision = self
+            .stop_sequences
+            .iter()
+            .enumerate()
+            .filter(|(_, sequence)| !sequence.is_empty())
+            .filter_map(|(index, sequence)| {
+                self.tail
+                    .match_indices(sequence.as_str())
+                    .find(|(start, _)| start + sequence.len() > new_start)
+                    .map(|(start, _)| (start, index))
+            })
+            .min()
+            .map_or(StopDecision::Continue, |(start, index)| {
+                StopDecision::Stop {
+                    index,
+                    trim: self.tail.len() - start,
+                }
+            });
+
+        let mut cut = self
+            .tail
+            .len()
+            .saturating_sub(self.max_len.saturating_sub(1));
+        while !self.tail.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        self.tail.drain(..cut);
+
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedTokenizer;
+
+    fn tokenizer(tokens: &[&[u8]]) -> Tokenizer {
+        let mut tokenizer = EmbeddedTokenizer::default();
+        for (id, token) in tokens.iter().enumerate() {
+            tokenizer.push_token(id as TokenId, token.to_vec(), 0.0);
+        }
+        tokenizer.into()
+    }
+
+    #[test]
+    fn test_stop_sequence_across_tokens() {
+        let tokenizer = tokenizer(&[b"<unk>", b"Hi", b"\n", b"\nUs", b"er:"]);
+        let mut matcher = StopSequenceMatcher::new(&tokenizer, &["\n\nUser:".to_string()]);
+
+        assert_eq!(matcher.push(1), StopDecision::Continue);
+        assert_eq!(matcher.push(2), StopDecision::Continue);
+        assert_eq!(matcher.push(3), StopDecision::Continue);
+        assert_eq!(matcher.push(4), StopDecision::Stop { index: 0, trim: 7 });
+    }
+
+    #[test]
+    fn test_stop_sequence_within_token() {
+        let tokenizer = tokenizer(&[b"<unk>", b"Hello", b" wor", b"ld! Bye"]);
+        let stop_sequences = ["Bye".to_string(), "world".to_string()];
+        let mut matcher = StopSequenceMatcher::new(&tokenizer, &stop_sequences);
+
+        assert_eq!(matcher.push(1), StopDecision::Continue);
+        assert_eq!(matcher.push(2), StopDecision::Continue);
+        // Both sequences are completed; "world" starts first, and is followed by "! Bye".
+        assert_eq!(matcher.push(3), StopDecision::Stop { index: 1, trim: 10 });
+    }
+}
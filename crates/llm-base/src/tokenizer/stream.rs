@@ -0,0 +1,304 @@
+use crate::TokenUtf8Buffer;
+
+use super::{embedded::SENTENCEPIECE_SPACE, TokenId, Tokenizer};
+
+/// The number of already-emitted tokens that are kept around to decode new Hugging Face
+/// tokens in context.
+const LOOKBACK: usize = 4;
+
+/// The most Hugging Face tokens to wait for when they decode to an incomplete character,
+/// as a UTF-8 character is at most four bytes long. After that, the text is emitted as is.
+const MAX_PENDING: usize = 4;
+
+/// Decodes a stream of token IDs into text as they are produced.
+///
+/// Unlike decoding each token with [Tokenizer::token], this produces the same text as
+/// decoding all of the tokens at once:
+/// - tokens that are not valid UTF-8 by themselves are buffered until they are
+///   (see [TokenUtf8Buffer]);
+/// - for embedded tokenizers, the SentencePiece metasymbol (`▁`) is converted to a space,
+///   except at the very beginning of the text, where the space of the dummy prefix is
+///   also dropped, and the beginning-of-string token is skipped, as in
+///   [Tokenizer::decode_normalized];
+/// - for Hugging Face tokenizers, new tokens are decoded together with the last few
+///   tokens, so that the tokenizer's decoder can restore the spacing between them.
+pub struct StreamDecoder<'a> {
+    tokenizer: &'a Tokenizer,
+    /// The bytes of the embedded tokens that do not form valid UTF-8 yet.
+    buffer: TokenUtf8Buffer,
+    /// The last Hugging Face tokens that were fed, oldest first.
+    window: Vec<TokenId>,
+    /// How many of the tokens in `window` have already been emitted.
+    emitted: usize,
+    /// Whether any bytes of embedded tokens have been produced yet.
+    started: bool,
+}
+impl<'a> StreamDecoder<'a> {
+    /// Create a new decoder for tokens from `tokenizer`.
+    pub fn new(tokenizer: &'a Tokenizer) -> Self {
+        Self {
+            tokenizer,
+            buffer: TokenUtf8Buffer::new(),
+            window: vec![],
+            emitted: 0,
+            started: false,
+        }
+    }
+
+    /// Feed the next `ids` to the decoder, and return the text that they complete.
+    ///
+    /// The returned text may be empty if the tokens do not form a complete character yet;
+    /// it will be returned by a later call once they do, or by [Self::finish].
+    pub fn feed(&mut self, ids: &[TokenId]) -> String {
+        match self.tokenizer {
+            Tokenizer::Embedded(_) => ids.iter().map(|&id| self.feed_embedded(id)).collect(),
+            Tokenizer::HuggingFace(_) => self.feed_huggingface(ids),
+        }
+    }
+
+    /// Return the text of the tokens that were held back because they did not form a
+    /// complete character, once no more tokens will be fed.
+    ///
+    /// Incomplete characters are replaced with the replacement character (`U+FFFD`).
+    pub fn finish(&mut self) -> String {
+        match self.tokenizer {
+            Tokenizer::Embedded(_) => String::from_utf8_lossy(&self.buffer.take()).into_owned(),
+            Tokenizer::HuggingFace(_) => {
+                let text = self.decode_pending();
+                self.emitted = self.window.len();
+                text
+            }
+        }
+    }
+
+    fn feed_embedded(&mut self, id: TokenId) -> String {
+        let Tokenizer::Embedded(tokenizer) = self.tokenizer else {
+            unreachable!("only embedded tokens are fed here");
+        };
+        // Skip the beginning-of-string token, as decoding with `skip_special_tokens` does.
+        if id == 1 {
+            return String::new();
+        }
+        let token = tokenizer.token(id as usize);
+
+        let mut bytes = Vec::with_capacity(token.len());
+        let mut rest = token.as_slice();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(SENTENCEPIECE_SPACE) {
+                // A leading metasymbol marks a word boundary, which is not a space
+                // at the very beginning of the text.
+                if self.started || !bytes.is_empty() {
+                    bytes.push(b' ');
+                }
+                rest = after;
+            } else {
+                bytes.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+
+        // The space at the very beginning of the text was added by the dummy prefix.
+        if !self.started && tokenizer.add_dummy_prefix() && bytes.first() == Some(&b' ') {
+            bytes.remove(0);
+        }
+        self.started |= !bytes.is_empty();
+        self.buffer.push(&bytes).unwrap_or_default()
+    }
+
+    fn feed_huggingface(&mut self, ids: &[TokenId]) -> String {
+        self.window.extend_from_slice(ids);
+        let text = self.decode_pending();
+
+        // Byte-level tokens that do not form a complete character yet are decoded to the
+        // replacement character; wait for the rest of the character, unless it has not
+        // come after several tokens, as the model may really have produced it.
+        let pending = self.window.len() - self.emitted;
+        if text.ends_with(char::REPLACEMENT_CHARACTER) && pending < MAX_PENDING {
+            return String::new();
+        }
+
+        let excess = self.window.len().saturating_sub(LOOKBACK);
+        self.window.drain(..excess);
+        self.emitted = self.window.len();
+
+        text
+    }
+
+    /// Decode the Hugging Face tokens that have not been emitted yet, in the context of
+    /// the ones that have.
+    fn decode_pending(&self) -> String {
+        let decode = |ids: &[TokenId]| {
+            String::from_utf8(self.tokenizer.decode(ids.to_vec(), true))
+                .expect("the Hugging Face tokenizer always decodes to valid UTF-8")
+        };
+        let previous = decode(&self.window[..self.emitted]);
+        let current = decode(&self.window);
+
+        // If decoding in context changed the text that was already emitted, it cannot be
+        // taken back, so decode the new tokens by themselves.
+        match current.strip_prefix(previous.as_str()) {
+            Some(text) => text.to_owned(),
+            None => decode(&self.window[self.emitted..]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmbeddedTokenizer;
+
+    /// An embedded tokenizer with the unknown and beginning-of-string tokens, followed by
+    /// `tokens` from ID 2.
+    fn tokenizer(tokens: &[&[u8]]) -> Tokenizer {
+        let mut tokenizer = EmbeddedTokenizer::default();
+        let tokens = [&b"<unk>"[..], b"<s>"]
+            .into_iter()
+            .chain(tokens.iter().copied());
+        for (id, token) in tokens.enumerate() {
+            tokenizer.push_token(id as TokenId, token.to_vec(), 0.0);
+        }
+        tokenizer.into()
+    }
+
+    #[test]
+    fn test_word_split_across_tokens() {
+        let tokenizer = tokenizer(&["▁Hel".as_bytes(), b"lo", "▁world".as_bytes()]);
+        let mut decoder = StreamDecoder::new(&tokenizer);
+
+        assert_eq!(decoder.feed(&[1, 2]), "Hel");
+        assert_eq!(decoder.feed(&[3]), "lo");
+        assert_eq!(decoder.feed(&[4]), " world");
+        assert_eq!(decoder.feed(&[2, 3]), " Hello");
+    }
+
+    #[test]
+    fn test_character_split_across_tokens() {
+        let tokenizer = tokenizer(&["▁".as_bytes(), &[0xE2, 0x82], &[0xAC]]);
+        let mut decoder = StreamDecoder::new(&tokenizer);
+
+        assert_eq!(decoder.feed(&[3]), "");
+        assert_eq!(decoder.feed(&[4]), "€");
+        assert_eq!(decoder.feed(&[2, 3, 4]), " €");
+    }
+
+    #[test]
+    fn test_finish_flushes_incomplete_character() {
+        let tokenizer = tokenizer(&["▁Hel".as_bytes(), &[0xE2, 0x82]]);
+        let mut decoder = StreamDecoder::new(&tokenizer);
+
+        assert_eq!(decoder.feed(&[2, 3]), "Hel");
+        assert_eq!(decoder.finish(), "\u{FFFD}");
+        assert_eq!(decoder.finish(), "");
+    }
+
+    /// A Hugging Face tokenizer that decodes its tokens separated by spaces.
+    fn word_level_tokenizer(clean_up_tokenization_spaces: bool) -> Tokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "<unk>": 0, "a": 1, "\uFFFD": 2, "'": 3 },
+                "unk_token": "<unk>"
+            }
+        }"#;
+        let mut tokenizer = crate::HuggingFaceTokenizer::new(json.parse().unwrap());
+        tokenizer.set_clean_up_tokenization_spaces(clean_up_tokenization_spaces);
+        tokenizer.into()
+    }
+
+    #[test]
+    fn test_replacement_character_is_flushed() {
+        let tokenizer = word_level_tokenizer(false);
+        let mut decoder = StreamDecoder::new(&tokenizer);
+
+        assert_eq!(decoder.feed(&[1]), "a");
+        for _ in 1..MAX_PENDING {
+            assert_eq!(decoder.feed(&[2]), "");
+        }
+        assert_eq!(decoder.feed(&[2]), " \u{FFFD} \u{FFFD} \u{FFFD} \u{FFFD}");
+        assert!(decoder.window.len() <= LOOKBACK);
+        assert_eq!(decoder.feed(&[1]), " a");
+    }
+
+    #[test]
+    fn test_finish_flushes_held_back_tokens() {
+        let tokenizer = word_level_tokenizer(false);
+        let mut decoder = StreamDecoder::new(&tokenizer);
+
+        assert_eq!(decoder.feed(&[1]), "a");
+        assert_eq!(decoder.feed(&[2, 2]), "");
+        assert_eq!(decoder.finish(), " \u{FFFD} \u{FFFD}");
+        assert_eq!(decoder.finish(), "");
+        assert_eq!(decoder.feed(&[1]), " a");
+    }
+
+    #[test]
+    fn test_changed_context_is_not_repeated() {
+        let tokenizer = word_level_tokenizer(true);
+        let mut decoder = StreamDecoder::new(&tokenizer);
+
+        // Cleaning up the spaces turns "a ' a" into "a'a", which does not start with the
+        // "a '" that was already emitted, so only the new token is emitted.
+        assert_eq!(decoder.feed(&[1, 3]), "a '");
+        assert_eq!(decoder.feed(&[1]), "a");
+    }
+
+    #[test]
+    fn test_decode_iter_matches_decode_normalized() {
+        let mut embedded = EmbeddedTokenizer::default();
+        for (id, token) in ["<unk>", "<s>", "</s>", " Hello", " world", "▁again"]
+            .iter()
+            .enumerate()
+        {
+            embedded.push_token(id as TokenId, token.as_bytes().to_vec(), 0.0);
+        }
+        embedded.set_add_dummy_prefix(true);
+
+        let embedded_ids = [&[1, 3, 4, 5][..], &[5, 3], &[3, 1, 4], &[1, 1, 2]];
+        let huggingface_ids = [&[1, 3, 1][..], &[3], &[1, 2, 1]];
+        for (tokenizer, ids) in [
+            (Tokenizer::from(embedded), &embedded_ids[..]),
+            (word_level_tokenizer(false), &huggingface_ids[..]),
+        ] {
+            for ids in ids {
+                let streamed: String = tokenizer.decode_iter(ids.iter().copied()).collect();
+                let decoded = tokenizer.decode_normalized(ids.to_vec(), true);
+                assert_eq!(streamed.as_bytes(), decoded, "decoding {ids:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_iter() {
+        let tokenizer = tokenizer(&["▁Hel".as_bytes(), b"lo", &[0xE2, 0x82], &[0xAC]]);
+
+        let chunks: Vec<_> = tokenizer
+            .decode_iter([1, 2, 3, 4, 5, 2].into_iter())
+            .collect();
+        assert_eq!(chunks, ["Hel", "lo", "€", " Hel"]);
+    }
+
+    #[test]
+    fn test_decode_iter_flushes_incomplete_character() {
+        let tokenizer = tokenizer(&["▁Hel".as_bytes(), &[0xE2, 0x82]]);
+
+        let chunks: Vec<_> = tokenizer.decode_iter([2, 3].into_iter()).collect();
+        assert_eq!(chunks, ["Hel", "\u{FFFD}"]);
+    }
+
+    #[test]
+    fn test_decode_iter_flushes_replacement_character() {
+        let tokenizer = word_level_tokenizer(false);
+
+        let text: String = tokenizer.decode_iter([1, 2].into_iter()).collect();
+        assert_eq!(text, "a \u{FFFD}");
+    }
+}
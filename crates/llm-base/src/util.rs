@@ -71,6 +71,11 @@ impl TokenUtf8Buffer {
             }
         }
     }
+
+    /// Take the bytes that do not form valid UTF-8 yet, leaving the buffer empty.
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -119,12 +119,7 @@ fn get_embeddings(
     };
     let vocab = model.tokenizer();
     let beginning_of_sentence = true;
-    let query_token_ids = vocab
-        .tokenize(query, beginning_of_sentence)
-        .unwrap()
-        .iter()
-        .map(|(_, tok)| *tok)
-        .collect::<Vec<_>>();
+    let query_token_ids = vocab.tokenize_ids(query, beginning_of_sentence).unwrap();
     model.evaluate(&mut session, &query_token_ids, &mut output_request);
     output_request.embeddings.unwrap()
 }
@@ -77,16 +77,18 @@ use std::{
 // Try not to expose too many GGML details here.
 // This is the "user-facing" API, and GGML may not always be our backend.
 pub use llm_base::{
-    conversation_inference_callback, feed_prompt_callback,
+    compare_tokenizations, conversation_inference_callback, feed_prompt_callback,
     ggml::accelerator::get_accelerator as ggml_get_accelerator,
     ggml::accelerator::Accelerator as GgmlAccelerator, ggml::format as ggml_format,
-    ggml::RoPEOverrides, load, load_progress_callback_stdout, quantize, samplers, ElementType,
-    FileType, FileTypeFormat, FormatMagic, Hyperparameters, InferenceError, InferenceFeedback,
-    InferenceParameters, InferenceRequest, InferenceResponse, InferenceSession,
+    ggml::RoPEOverrides, load, load_progress_callback_stdout, quantize, samplers, ChatTemplate,
+    ElementType, FileType, FileTypeFormat, FormatMagic, Hyperparameters, InferenceError,
+    InferenceFeedback, InferenceParameters, InferenceRequest, InferenceResponse, InferenceSession,
     InferenceSessionConfig, InferenceSnapshot, InferenceSnapshotRef, InferenceStats,
     InvalidTokenBias, KnownModel, LoadError, LoadProgress, Loader, Model, ModelKVMemoryType,
-    ModelParameters, OutputRequest, Prompt, QuantizeError, QuantizeProgress, RewindError,
-    SnapshotError, TokenBias, TokenId, TokenUtf8Buffer, TokenizationError, Tokenizer,
+    ModelParameters, OutputRequest, Prompt, PromptBuilder, PromptFormat, QuantizeError,
+    QuantizeProgress, RewindError, SnapshotError, StopDecision, StopSequenceMatcher, StreamDecoder,
+    TokenBias, TokenId, TokenUtf8Buffer, TokenizationDivergence, TokenizationError, Tokenizer,
+    TokenizerFallbackError, TokenizerHandle, TokenizerLoadError, TokenizerLoadErrorKind,
     TokenizerSource,
 };
 
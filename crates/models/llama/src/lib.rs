@@ -161,7 +161,8 @@ impl KnownModel for Llama {
             n_rot,
             file_type: _,
         } = self.hyperparameters;
-        let n_embd_gqa = n_embd / (n_head / n_head_kv);
+        let head_dim = self.hyperparameters.head_dim();
+        let n_embd_gqa = self.hyperparameters.n_embd_gqa();
 
         let outputs = session.compute(self.context.clone(), input_tokens, |builder| {
             let mut ctx0 = builder.ctx0.borrow_mut();
@@ -192,7 +193,7 @@ impl KnownModel for Llama {
                     .op_rope_inplace(
                         &ctx0.op_reshape_3d(
                             &ctx0.op_mul_mat(&self.layers[il].wq, &current),
-                            n_embd / n_head,
+                            head_dim,
                             n_head,
                             input_len,
                         ),
@@ -206,7 +207,7 @@ impl KnownModel for Llama {
                     .op_rope_inplace(
                         &ctx0.op_reshape_3d(
                             &ctx0.op_mul_mat(&self.layers[il].wk, &current),
-                            n_embd / n_head,
+                            head_dim,
                             n_head_kv,
                             input_len,
                         ),
@@ -253,7 +254,7 @@ impl KnownModel for Llama {
                                 (session_len + input_len) * n_embd_gqa,
                                 il * ctx_size * builder.memory_k.element_size() * n_embd_gqa,
                             ),
-                            n_embd / n_head,
+                            head_dim,
                             n_head_kv,
                             session_len + input_len,
                         ),
@@ -264,10 +265,10 @@ impl KnownModel for Llama {
                 // K * Q
                 let k_q = ctx0.op_mul_mat(&k, &q).set_name("KQ");
 
-                // KQ_scaled = KQ / sqrt(n_embd/n_head)
+                // KQ_scaled = KQ / sqrt(head_dim)
                 let kq_scale = ctx0
-                    .new_f32(1.0 / ((n_embd as f32 / n_head as f32).sqrt()))
-                    .set_name("1/sqrt(n_embd/n_head)");
+                    .new_f32(1.0 / (head_dim as f32).sqrt())
+                    .set_name("1/sqrt(head_dim)");
                 let k_q_scaled = ctx0.op_scale_inplace(&k_q, &kq_scale).set_name("KQ_scaled");
 
                 // KQ_masked = mask_past(KQ_scaled)
@@ -284,10 +285,10 @@ impl KnownModel for Llama {
                 let v = ctx0
                     .op_view_3d(
                         builder.memory_v,
-                        (session_len + input_len, n_embd / n_head, n_head_kv),
+                        (session_len + input_len, head_dim, n_head_kv),
                         (
                             ctx_size * builder.memory_v.element_size(),
-                            ctx_size * builder.memory_v.element_size() * n_embd / n_head,
+                            ctx_size * builder.memory_v.element_size() * head_dim,
                         ),
                         il * ctx_size * builder.memory_v.element_size() * n_embd_gqa,
                     )
@@ -421,6 +422,25 @@ pub struct Hyperparameters {
     pub file_type: FileType,
 }
 
+impl Hyperparameters {
+    /// The size of each attention head.
+    pub fn head_dim(&self) -> usize {
+        self.n_embd / self.n_head
+    }
+
+    /// The number of query heads that share each key and value head with grouped-query
+    /// attention, or 1 with multi-head attention.
+    pub fn n_gqa(&self) -> usize {
+        self.n_head / self.n_head_kv
+    }
+
+    /// The size of the key and value embeddings, which is smaller than `n_embd` with
+    /// grouped-query attention.
+    pub fn n_embd_gqa(&self) -> usize {
+        self.n_embd / self.n_gqa()
+    }
+}
+
 impl llm_base::Hyperparameters for Hyperparameters {
     fn read_ggml(reader: &mut dyn std::io::BufRead) -> Result<Self, LoadError> {
         let n_vocab = util::read_i32(reader)?.try_into()?;
@@ -434,6 +454,15 @@ impl llm_base::Hyperparameters for Hyperparameters {
         // Defaults to multi-head attention where n_head_kv == n_heads
         let n_head_kv = n_head;
 
+        if n_head == 0 || n_embd % n_head != 0 {
+            return Err(LoadError::InvariantBroken {
+                path: None,
+                invariant: format!(
+                    "LLaMA model expected n_embd {n_embd} to be divisible by n_head {n_head}"
+                ),
+            });
+        }
+
         Ok(Hyperparameters {
             n_head,
             n_head_kv,
@@ -496,3 +525,30 @@ enum LlamaModelType {
     Model65b,
     Model70b,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm_base::Hyperparameters as _;
+
+    #[test]
+    fn test_read_ggml_rejects_indivisible_n_embd() {
+        let hyperparameters = Hyperparameters {
+            n_vocab: 32,
+            n_embd: 10,
+            n_head: 3,
+            n_head_kv: 3,
+            n_layer: 1,
+            n_rot: 2,
+            ..Default::default()
+        };
+        let mut buffer = vec![];
+        hyperparameters.write_ggml(&mut buffer).unwrap();
+
+        let err = Hyperparameters::read_ggml(&mut buffer.as_slice()).unwrap_err();
+        assert!(
+            matches!(&err, LoadError::InvariantBroken { invariant, .. } if invariant.contains("n_embd 10")),
+            "expected a broken invariant, got {err:?}"
+        );
+    }
+}